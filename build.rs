@@ -0,0 +1,62 @@
+//! Probes the compiler for the `PanicInfo::message` API to use.
+//!
+//! Since Rust 1.81 `PanicInfo::message` is stable and returns a `PanicMessage`. Before, it returned
+//! an `Option<&fmt::Arguments>`, and was only available on nightly behind `feature(panic_info_message)`.
+//!
+//! Also tells whether the target has the fault status registers printed on a HardFault, and whether it is a Cortex-M
+//! (where `bkpt 0xab` makes a semihosting call).
+
+use std::env;
+use std::process::Command;
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=RUSTC");
+    println!("cargo:rustc-check-cfg=cfg(panic_info_message_legacy)");
+    println!("cargo:rustc-check-cfg=cfg(panic_info_display)");
+    println!("cargo:rustc-check-cfg=cfg(fault_status)");
+    println!("cargo:rustc-check-cfg=cfg(cortex_m)");
+
+    let target = env::var("TARGET").unwrap_or_default();
+    if ["thumbv6m", "thumbv7m", "thumbv7em", "thumbv8m"]
+        .iter()
+        .any(|arch| target.starts_with(arch))
+    {
+        println!("cargo:rustc-cfg=cortex_m");
+    }
+
+    // CFSR, HFSR, MMFAR and BFAR exist on ARMv7-M and ARMv8-M Mainline, but not on ARMv6-M or ARMv8-M Baseline.
+    if ["thumbv7m", "thumbv7em", "thumbv8m.main"]
+        .iter()
+        .any(|arch| target.starts_with(arch))
+    {
+        println!("cargo:rustc-cfg=fault_status");
+    }
+
+    let Some((minor, nightly)) = rustc_version() else {
+        // Assume a recent compiler if the version cannot be determined.
+        return;
+    };
+
+    if minor < 81 {
+        if nightly {
+            // Old nightly: `message()` returns `Option<&fmt::Arguments>` and needs the feature.
+            println!("cargo:rustc-cfg=panic_info_message_legacy");
+        } else {
+            // Old stable: the message is only available as part of `PanicInfo`'s `Display`.
+            println!("cargo:rustc-cfg=panic_info_display");
+        }
+    }
+}
+
+/// Returns the minor version of the compiler, and whether it is a nightly (or dev) build.
+fn rustc_version() -> Option<(u32, bool)> {
+    let rustc = env::var_os("RUSTC").unwrap_or_else(|| "rustc".into());
+    let output = Command::new(rustc).arg("--version").output().ok()?;
+    let version = String::from_utf8(output.stdout).ok()?;
+    // e.g. "rustc 1.80.0-nightly (...)"
+    let version = version.split_whitespace().nth(1)?;
+    let minor = version.split('.').nth(1)?.parse().ok()?;
+    let nightly = version.contains("-nightly") || version.contains("-dev");
+    Some((minor, nightly))
+}
@@ -0,0 +1,220 @@
+//! Telling panic reports apart from the rest of the serial output.
+
+use crate::{Frame, Template};
+
+/// What a line of serial output is, as far as panics are concerned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    /// Output of the firmware, not part of a report.
+    Traffic,
+    /// The report of a panic.
+    Panic,
+    /// The registers of a HardFault.
+    HardFault,
+    /// Any other part of a report: the sentinel, firmware id, breadcrumbs, backtrace or memory regions.
+    Detail,
+}
+
+/// Classifies lines of serial output, keeping track of reports spanning several lines.
+#[derive(Clone, Debug)]
+pub struct Classifier {
+    template: Template,
+    /// Whether the previous line belonged to a report.
+    in_report: bool,
+    /// Whether the report was announced (by the sentinel or firmware id), but the panic not printed yet.
+    expect_panic: bool,
+}
+
+impl Classifier {
+    /// Creates a classifier for reports printed with the given template.
+    pub fn new(template: Template) -> Self {
+        Classifier {
+            template,
+            in_report: false,
+            expect_panic: false,
+        }
+    }
+
+    /// Classifies a line of text, as printed without the `binary` feature.
+    ///
+    /// Panics printed with only the `message` feature cannot be told from other output, unless they follow
+    /// the sentinel or the firmware id.
+    pub fn classify(&mut self, line: &str) -> Kind {
+        let text = strip_timestamp(line.trim_end_matches(['\r', '\n']));
+        let template = &self.template;
+        let kind = if (!template.sentinel.is_empty() && text == template.sentinel)
+            || self.is_labelled(text, "Firmware")
+        {
+            self.expect_panic = true;
+            Kind::Detail
+        } else if self.is_labelled(text, "HardFault") {
+            Kind::HardFault
+        } else if (!template.prefix.is_empty() && text.starts_with(&template.prefix))
+            || text == template.fallback
+            || (self.expect_panic && !self.is_detail(text))
+        {
+            Kind::Panic
+        } else if self.in_report && self.is_detail(text) {
+            Kind::Detail
+        } else {
+            Kind::Traffic
+        };
+        self.update(kind)
+    }
+
+    /// Classifies a decoded frame.
+    pub fn classify_frame(&mut self, frame: &Frame) -> Kind {
+        let kind = match frame {
+            Frame::Panic(_) => Kind::Panic,
+            Frame::HardFault(_) => Kind::HardFault,
+            Frame::Firmware(_) | Frame::Timestamp(_) => {
+                self.expect_panic = true;
+                Kind::Detail
+            }
+            Frame::Backtrace(_) | Frame::Region(_) | Frame::Breadcrumb(_) => Kind::Detail,
+        };
+        self.update(kind)
+    }
+
+    fn update(&mut self, kind: Kind) -> Kind {
+        self.in_report = kind != Kind::Traffic;
+        if matches!(kind, Kind::Traffic | Kind::Panic | Kind::HardFault) {
+            self.expect_panic = false;
+        }
+        kind
+    }
+
+    /// Whether the line starts with `label`, followed by the separator (like `Breadcrumb: `).
+    fn is_labelled(&self, text: &str, label: &str) -> bool {
+        text.strip_prefix(label)
+            .is_some_and(|rest| rest.starts_with(&self.template.separator))
+    }
+
+    /// Whether the line is a part of a report which follows the panic.
+    fn is_detail(&self, text: &str) -> bool {
+        self.is_labelled(text, "Breadcrumb")
+            || text.starts_with("Backtrace")
+            || text.starts_with("Region ")
+            || is_dump(text)
+    }
+}
+
+impl Default for Classifier {
+    /// A classifier for the defaults of `Config`.
+    fn default() -> Self {
+        Classifier::new(Template::default())
+    }
+}
+
+/// Strips the timestamp (see `Config::timestamp`) off a line.
+fn strip_timestamp(line: &str) -> &str {
+    line.strip_prefix('[')
+        .and_then(|rest| rest.split_once("] "))
+        .filter(|(timestamp, _)| {
+            !timestamp.is_empty() && timestamp.bytes().all(|c| c.is_ascii_digit())
+        })
+        .map_or(line, |(_, rest)| rest)
+}
+
+/// Whether the line is a line of bytes of a memory region: `20000100 00 01 02`.
+fn is_dump(text: &str) -> bool {
+    let Some((address, bytes)) = text.split_once(' ') else {
+        return false;
+    };
+    address.len() == 8
+        && address.bytes().all(|c| c.is_ascii_hexdigit())
+        && bytes
+            .split(' ')
+            .all(|byte| byte.len() == 2 && byte.bytes().all(|c| c.is_ascii_hexdigit()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn classify(classifier: &mut Classifier, lines: &str) -> Vec<Kind> {
+        lines
+            .lines()
+            .map(|line| classifier.classify(line))
+            .collect()
+    }
+
+    #[test]
+    fn finds_text_report() {
+        let mut classifier = Classifier::default();
+        let lines = "booting\r\n\
+                     temperature: 21\r\n\
+                     !!! PANIC\r\n\
+                     Firmware: blinky 0.3.1\r\n\
+                     [86400123] Panic at src/main.rs:91:9: attempt to subtract with overflow\r\n\
+                     Breadcrumb: entering state 3\r\n\
+                     Backtrace: 0x08000ddd 0x08001e41\r\n\
+                     Region state at 0x20000100 (20 bytes)\r\n\
+                     20000100 00 01 02 03 04 05 06 07 08 09 0a 0b 0c 0d 0e 0f\r\n\
+                     20000110 a0 b1 c2 d3\r\n\
+                     booting\r\n\
+                     20000110 a0 b1 c2 d3\r\n";
+        use Kind::*;
+        assert_eq!(
+            classify(&mut classifier, lines),
+            [
+                Traffic, Traffic, Detail, Detail, Panic, Detail, Detail, Detail, Detail, Detail,
+                Traffic, Traffic
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_message_after_sentinel() {
+        let mut classifier = Classifier::default();
+        let lines = "attempt to subtract with overflow\n\
+                     !!! PANIC\n\
+                     attempt to subtract with overflow\n\
+                     HardFault: r0=0x00000000\n\
+                     PANIC !\n\
+                     panicked at src/main.rs:1:1\n";
+        use Kind::*;
+        assert_eq!(
+            classify(&mut classifier, lines),
+            [Traffic, Detail, Panic, HardFault, Panic, Traffic]
+        );
+
+        let mut classifier = Classifier::new(Template {
+            prefix: "panicked at ".into(),
+            sentinel: "".into(),
+            ..Template::default()
+        });
+        assert_eq!(
+            classify(&mut classifier, "!!! PANIC\npanicked at src/main.rs:1:1\n"),
+            [Traffic, Panic]
+        );
+    }
+
+    #[test]
+    fn follows_separator_and_fallback() {
+        let mut classifier = Classifier::new(Template {
+            separator: " - ".into(),
+            fallback: "oops".into(),
+            ..Template::default()
+        });
+        let lines = "Firmware - blinky 0.3.1\n\
+                     Panic at src/main.rs:91:9 - boom\n\
+                     Breadcrumb - entering state 3\n\
+                     Breadcrumb: entering state 3\n\
+                     oops\n\
+                     HardFault - r0=0x00000000\n\
+                     PANIC !\n";
+        use Kind::*;
+        assert_eq!(
+            classify(&mut classifier, lines),
+            [Detail, Panic, Detail, Traffic, Panic, HardFault, Traffic]
+        );
+    }
+
+    #[test]
+    fn strips_timestamps() {
+        assert_eq!(strip_timestamp("[12] Panic at"), "Panic at");
+        assert_eq!(strip_timestamp("[] Panic at"), "[] Panic at");
+        assert_eq!(strip_timestamp("[ok] done"), "[ok] done");
+    }
+}
@@ -0,0 +1,585 @@
+//! Decodes the binary panic (and HardFault) frames printed by `panic-serial` with the `binary` feature.
+//!
+//! See [`panic_serial::frame`] for the format.
+//!
+//! Also translates file hashes (in frames, and in text reports printed with the `file-hash` feature) back to file names,
+//! see [`PathMap`], and symbolizes backtraces (printed with the `backtrace` feature), see [`Symbols`].
+//!
+//! [`Classifier`] tells panic reports apart from the rest of the output, for `panic-serial-monitor`.
+
+use panic_serial::frame::{
+    crc16, KIND_BACKTRACE, KIND_BREADCRUMB, KIND_FIRMWARE, KIND_HARD_FAULT, KIND_PANIC,
+    KIND_REGION, KIND_TIMESTAMP, START,
+};
+use std::fmt::{self, Display};
+
+mod classify;
+mod paths;
+mod symbols;
+
+pub use classify::{Classifier, Kind};
+pub use paths::PathMap;
+pub use symbols::Symbols;
+
+/// The parts of text reports which the firmware can change through `Config`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Template {
+    /// `Config::PREFIX`, before the location.
+    pub prefix: String,
+    /// `Config::SEPARATOR`, between the location and the message, and after labels like `Firmware`.
+    pub separator: String,
+    /// `Config::FALLBACK_MESSAGE`, printed if there is neither location nor message.
+    pub fallback: String,
+    /// `Config::SENTINEL`, printed before a report which interrupted a line.
+    pub sentinel: String,
+}
+
+impl Default for Template {
+    /// The defaults of `Config`.
+    fn default() -> Self {
+        Template {
+            prefix: "Panic at ".into(),
+            separator: ": ".into(),
+            fallback: "PANIC !".into(),
+            sentinel: "!!! PANIC".into(),
+        }
+    }
+}
+
+/// A decoded frame.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Frame {
+    Panic(Report),
+    HardFault(Fault),
+    /// The return addresses found on the stack.
+    Backtrace(Vec<u32>),
+    Region(Region),
+    Breadcrumb(String),
+    Firmware(Firmware),
+    /// The time of the panic (see `Config::timestamp`), which prefixes the next frame.
+    Timestamp(u64),
+}
+
+impl Frame {
+    /// Prints the frame like `panic-serial` prints it in text form, with the prefix and separator of `template`.
+    pub fn display<'a>(&'a self, template: &'a Template) -> Templated<'a, Frame> {
+        Templated(self, template)
+    }
+}
+
+/// Prints a frame (or a part of one) like `panic-serial` prints it in text form, with the given [`Template`].
+pub struct Templated<'a, T>(&'a T, &'a Template);
+
+impl Display for Templated<'_, Frame> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Templated(frame, template) = *self;
+        match frame {
+            Frame::Panic(report) => report.display(template).fmt(f),
+            Frame::HardFault(fault) => fault.display(template).fmt(f),
+            Frame::Backtrace(addresses) => {
+                f.write_str("Backtrace")?;
+                for (i, address) in addresses.iter().enumerate() {
+                    let separator = if i == 0 {
+                        template.separator.as_str()
+                    } else {
+                        " "
+                    };
+                    write!(f, "{separator}0x{address:08x}")?;
+                }
+                Ok(())
+            }
+            Frame::Region(region) => region.fmt(f),
+            Frame::Breadcrumb(breadcrumb) => {
+                write!(f, "Breadcrumb{}{breadcrumb}", template.separator)
+            }
+            Frame::Firmware(firmware) => firmware.display(template).fmt(f),
+            Frame::Timestamp(timestamp) => write!(f, "[{timestamp}]"),
+        }
+    }
+}
+
+/// A decoded panic frame.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Report {
+    /// Line of the panic, `0` if no location was sent.
+    pub line: u32,
+    /// Column of the panic, `0` if no location was sent.
+    pub column: u32,
+    /// Hash of the file name (see [`panic_serial::frame::file_hash`]), `0` if no location was sent.
+    pub file_hash: u32,
+    /// The panic message, empty if none was sent.
+    pub message: String,
+}
+
+impl Report {
+    /// Whether the frame contained a location.
+    pub fn has_location(&self) -> bool {
+        self.line != 0
+    }
+}
+
+impl Report {
+    /// Prints the report like `panic-serial` prints text reports, with the file hash in place of the file name.
+    pub fn display<'a>(&'a self, template: &'a Template) -> Templated<'a, Report> {
+        Templated(self, template)
+    }
+}
+
+impl Display for Templated<'_, Report> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Templated(report, template) = *self;
+        if report.has_location() {
+            write!(
+                f,
+                "{}#{:08x}:{}:{}",
+                template.prefix, report.file_hash, report.line, report.column
+            )?;
+            if !report.message.is_empty() {
+                f.write_str(&template.separator)?;
+            }
+        } else if report.message.is_empty() {
+            return f.write_str(&template.fallback);
+        }
+        f.write_str(&report.message)
+    }
+}
+
+/// A decoded HardFault frame.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Fault {
+    /// The exception frame: r0, r1, r2, r3, r12, lr, pc and xpsr.
+    pub registers: [u32; 8],
+    /// The fault status registers CFSR, HFSR, MMFAR and BFAR, if the target has them.
+    pub status: Option<[u32; 4]>,
+}
+
+impl Fault {
+    const NAMES: [&'static str; 12] = [
+        "r0", "r1", "r2", "r3", "r12", "lr", "pc", "xpsr", "cfsr", "hfsr", "mmfar", "bfar",
+    ];
+
+    /// The program counter at the time of the fault.
+    pub fn pc(&self) -> u32 {
+        self.registers[6]
+    }
+}
+
+impl Fault {
+    /// Prints the registers like `panic-serial` does.
+    pub fn display<'a>(&'a self, template: &'a Template) -> Templated<'a, Fault> {
+        Templated(self, template)
+    }
+}
+
+impl Display for Templated<'_, Fault> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Templated(fault, template) = *self;
+        f.write_str("HardFault")?;
+        let registers = fault.registers.iter().chain(fault.status.iter().flatten());
+        for (i, (name, value)) in Fault::NAMES.iter().zip(registers).enumerate() {
+            let separator = if i == 0 {
+                template.separator.as_str()
+            } else {
+                " "
+            };
+            write!(f, "{separator}{name}=0x{value:08x}")?;
+        }
+        Ok(())
+    }
+}
+
+/// A decoded memory region.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub name: String,
+    pub address: u32,
+    pub bytes: Vec<u8>,
+}
+
+/// Prints the region like `panic-serial` does: a line with name, address and length, followed by 16 bytes per line.
+impl Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let len = self.bytes.len();
+        let unit = if len == 1 { "byte" } else { "bytes" };
+        write!(
+            f,
+            "Region {} at 0x{:08x} ({len} {unit})",
+            self.name, self.address
+        )?;
+        for (i, line) in self.bytes.chunks(16).enumerate() {
+            write!(f, "\n{:08x}", self.address.wrapping_add(16 * i as u32))?;
+            for byte in line {
+                write!(f, " {byte:02x}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A decoded firmware id.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Firmware {
+    /// The `FIRMWARE_ID` of the configuration, empty if it has none.
+    pub id: String,
+    /// The build id, empty if there is none.
+    pub build_id: Vec<u8>,
+}
+
+impl Firmware {
+    /// Prints the firmware id like `panic-serial` does.
+    pub fn display<'a>(&'a self, template: &'a Template) -> Templated<'a, Firmware> {
+        Templated(self, template)
+    }
+}
+
+impl Display for Templated<'_, Firmware> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Templated(firmware, template) = *self;
+        write!(f, "Firmware{}{}", template.separator, firmware.id)?;
+        if !firmware.id.is_empty() && !firmware.build_id.is_empty() {
+            f.write_str(" ")?;
+        }
+        for byte in &firmware.build_id {
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Why a frame could not be decoded.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The frame contains characters outside of the base64 alphabet.
+    InvalidBase64,
+    /// The frame ends before all fields were read.
+    Truncated,
+    /// The checksum does not match, so the frame was corrupted on its way.
+    Checksum { expected: u16, actual: u16 },
+    /// The frame is of a kind this decoder does not know.
+    UnknownKind(u8),
+    /// The message is not valid UTF-8.
+    InvalidMessage,
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidBase64 => f.write_str("invalid base64"),
+            Error::Truncated => f.write_str("frame is truncated"),
+            Error::Checksum { expected, actual } => {
+                write!(
+                    f,
+                    "checksum mismatch (expected {expected:04x}, got {actual:04x})"
+                )
+            }
+            Error::UnknownKind(kind) => write!(f, "unknown frame kind {kind}"),
+            Error::InvalidMessage => f.write_str("message is not valid UTF-8"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Splits a line of serial output into the text before a frame, and the frame (if there is one).
+pub fn split_line(line: &str) -> (&str, Option<&str>) {
+    match line.split_once(START) {
+        Some((text, frame)) => (text, Some(frame.trim_end_matches(['\r', '\n']))),
+        None => (line, None),
+    }
+}
+
+/// Decodes a frame, given the characters between the start character and the line break.
+pub fn decode(frame: &str) -> Result<Frame, Error> {
+    let bytes = base64(frame)?;
+    let (body, checksum) = bytes.split_at(bytes.len().checked_sub(2).ok_or(Error::Truncated)?);
+    let expected = u16::from_le_bytes([checksum[0], checksum[1]]);
+    let actual = crc16(body);
+    if expected != actual {
+        return Err(Error::Checksum { expected, actual });
+    }
+
+    let (&kind, rest) = body.split_first().ok_or(Error::Truncated)?;
+    match kind {
+        KIND_PANIC => decode_panic(rest).map(Frame::Panic),
+        KIND_HARD_FAULT => decode_hard_fault(rest).map(Frame::HardFault),
+        KIND_BACKTRACE => words(rest).map(Frame::Backtrace),
+        KIND_REGION => decode_region(rest).map(Frame::Region),
+        KIND_BREADCRUMB => String::from_utf8(rest.to_vec())
+            .map(Frame::Breadcrumb)
+            .map_err(|_| Error::InvalidMessage),
+        KIND_FIRMWARE => decode_firmware(rest).map(Frame::Firmware),
+        KIND_TIMESTAMP => rest
+            .try_into()
+            .map(|bytes| Frame::Timestamp(u64::from_le_bytes(bytes)))
+            .map_err(|_| Error::Truncated),
+        _ => Err(Error::UnknownKind(kind)),
+    }
+}
+
+fn decode_panic(mut rest: &[u8]) -> Result<Report, Error> {
+    let line = leb128(&mut rest)?;
+    let column = leb128(&mut rest)?;
+    let (hash, message) = rest.split_at_checked(4).ok_or(Error::Truncated)?;
+    let file_hash = u32::from_le_bytes(hash.try_into().unwrap());
+    let message = String::from_utf8(message.to_vec()).map_err(|_| Error::InvalidMessage)?;
+
+    Ok(Report {
+        line,
+        column,
+        file_hash,
+        message,
+    })
+}
+
+fn decode_hard_fault(rest: &[u8]) -> Result<Fault, Error> {
+    let words = words(rest)?;
+    // The exception frame, optionally followed by the fault status registers.
+    if words.len() != 8 && words.len() != 12 {
+        return Err(Error::Truncated);
+    }
+    Ok(Fault {
+        registers: words[..8].try_into().unwrap(),
+        status: words[8..].try_into().ok(),
+    })
+}
+
+fn decode_region(rest: &[u8]) -> Result<Region, Error> {
+    let (address, mut rest) = rest.split_at_checked(4).ok_or(Error::Truncated)?;
+    let address = u32::from_le_bytes(address.try_into().unwrap());
+    let name_len = leb128(&mut rest)? as usize;
+    let (name, bytes) = rest.split_at_checked(name_len).ok_or(Error::Truncated)?;
+    let name = String::from_utf8(name.to_vec()).map_err(|_| Error::InvalidMessage)?;
+    Ok(Region {
+        name,
+        address,
+        bytes: bytes.to_vec(),
+    })
+}
+
+fn decode_firmware(mut rest: &[u8]) -> Result<Firmware, Error> {
+    let id_len = leb128(&mut rest)? as usize;
+    let (id, build_id) = rest.split_at_checked(id_len).ok_or(Error::Truncated)?;
+    let id = String::from_utf8(id.to_vec()).map_err(|_| Error::InvalidMessage)?;
+    Ok(Firmware {
+        id,
+        build_id: build_id.to_vec(),
+    })
+}
+
+/// Reads 4 byte little endian words.
+fn words(bytes: &[u8]) -> Result<Vec<u32>, Error> {
+    let chunks = bytes.chunks_exact(4);
+    if !chunks.remainder().is_empty() {
+        return Err(Error::Truncated);
+    }
+    Ok(chunks
+        .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+        .collect())
+}
+
+/// Returns the addresses of a backtrace printed as text (`Backtrace: 0x08001235 0x08000f01`), or of a decoded one.
+pub fn backtrace_addresses(line: &str) -> Option<Vec<u32>> {
+    let rest = line.trim_start().strip_prefix("Backtrace")?;
+    Some(
+        rest.split_whitespace()
+            .filter_map(|word| word.strip_prefix("0x"))
+            .filter_map(|hex| u32::from_str_radix(hex, 16).ok())
+            .collect(),
+    )
+}
+
+fn leb128(bytes: &mut &[u8]) -> Result<u32, Error> {
+    let mut value = 0u32;
+    for shift in (0..35).step_by(7) {
+        let (&byte, rest) = bytes.split_first().ok_or(Error::Truncated)?;
+        *bytes = rest;
+        value |= ((byte & 0x7f) as u32) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(Error::Truncated)
+}
+
+fn base64(text: &str) -> Result<Vec<u8>, Error> {
+    let mut bytes = Vec::with_capacity(text.len() * 3 / 4);
+    let mut bits = 0u32;
+    let mut count = 0;
+    for c in text.bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return Err(Error::InvalidBase64),
+        };
+        bits = bits << 6 | value as u32;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            bytes.push((bits >> count) as u8);
+        }
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(frame: &Frame) -> String {
+        frame.display(&Template::default()).to_string()
+    }
+
+    /// r0-r3 = 0-3, r12 = 12, lr = 0x08000f01, pc = 0x08001234, xpsr = 0x61000000, cfsr = 0x8200,
+    /// hfsr = 0x40000000, mmfar = 0, bfar = 0x20008000.
+    const HARD_FAULT: &str = "AgAAAAABAAAAAgAAAAMAAAAMAAAAAQ8ACDQSAAgAAABhAIIAAAAAAEAAAAAAAIAAICNv";
+
+    /// The region "state" at 0x20000100, with the bytes 0-15 and a0 b1 c2 d3.
+    const REGION: &str = "BAABACAFc3RhdGUAAQIDBAUGBwgJCgsMDQ4PoLHC07zw";
+
+    #[test]
+    fn decodes_full_frame() {
+        let frame = decode("AVsJfEBgFmF0dGVtcHQgdG8gc3VidHJhY3Qgd2l0aCBvdmVyZmxvd2N9").unwrap();
+        assert_eq!(
+            frame,
+            Frame::Panic(Report {
+                line: 91,
+                column: 9,
+                file_hash: panic_serial::frame::file_hash("src/main.rs"),
+                message: "attempt to subtract with overflow".into(),
+            })
+        );
+        assert_eq!(
+            text(&frame),
+            "Panic at #1660407c:91:9: attempt to subtract with overflow"
+        );
+    }
+
+    #[test]
+    fn displays_with_template() {
+        let template = Template {
+            prefix: "panicked at ".into(),
+            separator: " - ".into(),
+            fallback: "oops".into(),
+            ..Template::default()
+        };
+        let frame = decode("AVsJfEBgFmF0dGVtcHQgdG8gc3VidHJhY3Qgd2l0aCBvdmVyZmxvd2N9").unwrap();
+        assert_eq!(
+            frame.display(&template).to_string(),
+            "panicked at #1660407c:91:9 - attempt to subtract with overflow"
+        );
+        let frame = decode("AQAAAAAAAK9J").unwrap();
+        assert_eq!(frame.display(&template).to_string(), "oops");
+        let frame = decode("BWVudGVyaW5nIHN0YXRlIDOGiA").unwrap();
+        assert_eq!(
+            frame.display(&template).to_string(),
+            "Breadcrumb - entering state 3"
+        );
+    }
+
+    #[test]
+    fn decodes_multi_byte_line() {
+        let Ok(Frame::Panic(report)) = decode("AawCDPpufLPiTA") else {
+            panic!("not a panic frame");
+        };
+        assert_eq!((report.line, report.column), (300, 12));
+        assert_eq!(
+            report.display(&Template::default()).to_string(),
+            "Panic at #b37c6efa:300:12"
+        );
+    }
+
+    #[test]
+    fn decodes_empty_frame() {
+        let Ok(Frame::Panic(report)) = decode("AQAAAAAAAK9J") else {
+            panic!("not a panic frame");
+        };
+        assert!(!report.has_location());
+        assert_eq!(report.display(&Template::default()).to_string(), "PANIC !");
+    }
+
+    #[test]
+    fn decodes_hard_fault_frame() {
+        let Ok(Frame::HardFault(fault)) = decode(HARD_FAULT) else {
+            panic!("not a HardFault frame");
+        };
+        assert_eq!(fault.pc(), 0x0800_1234);
+        assert_eq!(fault.status, Some([0x8200, 0x4000_0000, 0, 0x2000_8000]));
+        assert_eq!(
+            fault.display(&Template::default()).to_string(),
+            "HardFault: r0=0x00000000 r1=0x00000001 r2=0x00000002 r3=0x00000003 r12=0x0000000c \
+             lr=0x08000f01 pc=0x08001234 xpsr=0x61000000 \
+             cfsr=0x00008200 hfsr=0x40000000 mmfar=0x00000000 bfar=0x20008000"
+        );
+    }
+
+    #[test]
+    fn decodes_backtrace_frame() {
+        let frame = decode("AzUSAAgBDwAIbLU").unwrap();
+        assert_eq!(frame, Frame::Backtrace(vec![0x0800_1235, 0x0800_0f01]));
+        assert_eq!(text(&frame), "Backtrace: 0x08001235 0x08000f01");
+        assert_eq!(
+            backtrace_addresses(&text(&frame)),
+            Some(vec![0x0800_1235, 0x0800_0f01])
+        );
+        assert_eq!(backtrace_addresses("Panic at src/main.rs:91:9"), None);
+    }
+
+    #[test]
+    fn decodes_region_frame() {
+        let frame = decode(REGION).unwrap();
+        assert_eq!(
+            text(&frame),
+            "Region state at 0x20000100 (20 bytes)\n\
+             20000100 00 01 02 03 04 05 06 07 08 09 0a 0b 0c 0d 0e 0f\n\
+             20000110 a0 b1 c2 d3"
+        );
+    }
+
+    #[test]
+    fn decodes_firmware_frame() {
+        let frame = decode("BgxibGlua3kgMC4zLjE/KgHF6w").unwrap();
+        assert_eq!(
+            frame,
+            Frame::Firmware(Firmware {
+                id: "blinky 0.3.1".into(),
+                build_id: vec![0x3f, 0x2a, 0x01],
+            })
+        );
+        assert_eq!(text(&frame), "Firmware: blinky 0.3.1 3f2a01");
+    }
+
+    #[test]
+    fn decodes_timestamp_frame() {
+        let frame = decode("B3tcJgUAAAAAOOw").unwrap();
+        assert_eq!(frame, Frame::Timestamp(86_400_123));
+        assert_eq!(text(&frame), "[86400123]");
+    }
+
+    #[test]
+    fn decodes_breadcrumb_frame() {
+        let frame = decode("BWVudGVyaW5nIHN0YXRlIDOGiA").unwrap();
+        assert_eq!(text(&frame), "Breadcrumb: entering state 3");
+    }
+
+    #[test]
+    fn rejects_corrupted_frame() {
+        assert!(matches!(
+            decode("AQAAAAAAAK9K"),
+            Err(Error::Checksum { .. })
+        ));
+        assert_eq!(decode("AQ"), Err(Error::Truncated));
+        assert_eq!(decode("AQ!A"), Err(Error::InvalidBase64));
+    }
+
+    #[test]
+    fn splits_frame_from_text() {
+        assert_eq!(
+            split_line("booting\x01AQAAAAAAAK9J\r\n"),
+            ("booting", Some("AQAAAAAAAK9J"))
+        );
+        assert_eq!(split_line("hello\r\n"), ("hello\r\n", None));
+    }
+}
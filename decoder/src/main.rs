@@ -0,0 +1,138 @@
+//! Reads serial output (from a file, or stdin), and prints it with binary panic (and HardFault) frames decoded.
+//!
+//! Usage: `panic-serial-decode [--paths <FIRMWARE|SOURCE_DIR>]... [--elf <FIRMWARE>] [--prefix <PREFIX>]
+//! [--separator <SEPARATOR>] [--fallback <MESSAGE>] [FILE]`
+//!
+//! With `--paths`, hashed file names (from frames, or the `file-hash` feature) are translated back
+//! to file names found in the given firmware image or source tree.
+//!
+//! With `--elf`, the addresses of backtraces are symbolized using the debug information of the firmware,
+//! and its file names are used like with `--paths`.
+//!
+//! `--prefix`, `--separator` and `--fallback` print the decoded frames with the given `Config::PREFIX`,
+//! `Config::SEPARATOR` and `Config::FALLBACK_MESSAGE`, to match the rest of the output.
+
+use panic_serial_decoder::{
+    backtrace_addresses, decode, split_line, Frame, PathMap, Symbols, Template,
+};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+const USAGE: &str =
+    "usage: panic-serial-decode [--paths <FIRMWARE|SOURCE_DIR>]... [--elf <FIRMWARE>] \
+     [--prefix <PREFIX>] [--separator <SEPARATOR>] [--fallback <MESSAGE>] [FILE]";
+
+fn main() -> ExitCode {
+    let mut paths = PathMap::new();
+    let mut symbols = None;
+    let mut template = Template::default();
+    let mut file = None;
+    let mut args = std::env::args_os().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--paths" {
+            let Some(path) = args.next().map(PathBuf::from) else {
+                eprintln!("{USAGE}");
+                return ExitCode::FAILURE;
+            };
+            if let Err(err) = paths.add_path(&path) {
+                eprintln!("{}: {err}", path.display());
+                return ExitCode::FAILURE;
+            }
+        } else if arg == "--elf" {
+            let Some(path) = args.next().map(PathBuf::from) else {
+                eprintln!("{USAGE}");
+                return ExitCode::FAILURE;
+            };
+            if let Err(err) = paths.add_path(&path) {
+                eprintln!("{}: {err}", path.display());
+                return ExitCode::FAILURE;
+            }
+            match Symbols::load(&path) {
+                Ok(loaded) => symbols = Some(loaded),
+                Err(err) => {
+                    eprintln!("{}: {err}", path.display());
+                    return ExitCode::FAILURE;
+                }
+            }
+        } else if arg == "--prefix" || arg == "--separator" || arg == "--fallback" {
+            let Some(value) = args.next().and_then(|value| value.into_string().ok()) else {
+                eprintln!("{USAGE}");
+                return ExitCode::FAILURE;
+            };
+            match arg.to_str() {
+                Some("--prefix") => template.prefix = value,
+                Some("--separator") => template.separator = value,
+                _ => template.fallback = value,
+            }
+        } else if arg == "--help" || file.is_some() {
+            eprintln!("{USAGE}");
+            return ExitCode::FAILURE;
+        } else {
+            file = Some(PathBuf::from(arg));
+        }
+    }
+
+    let input: Box<dyn BufRead> = match file {
+        Some(path) => match File::open(&path) {
+            Ok(file) => Box::new(BufReader::new(file)),
+            Err(err) => {
+                eprintln!("{}: {err}", path.display());
+                return ExitCode::FAILURE;
+            }
+        },
+        None => Box::new(io::stdin().lock()),
+    };
+
+    match run(
+        input,
+        &mut io::stdout().lock(),
+        &paths,
+        symbols.as_ref(),
+        &template,
+    ) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(
+    mut input: impl BufRead,
+    output: &mut impl Write,
+    paths: &PathMap,
+    symbols: Option<&Symbols>,
+    template: &Template,
+) -> io::Result<()> {
+    let mut buf = Vec::new();
+    while input.read_until(b'\n', &mut buf)? > 0 {
+        // Serial output is not guaranteed to be valid UTF-8; frames are plain ASCII either way.
+        let line = String::from_utf8_lossy(&buf);
+        let report = match split_line(&line) {
+            (text, Some(frame)) => {
+                if !text.is_empty() {
+                    writeln!(output, "{}", paths.translate(text))?;
+                }
+                match decode(frame) {
+                    // Like in text reports, the timestamp goes in front of what follows.
+                    Ok(frame @ Frame::Timestamp(_)) => format!("{} ", frame.display(template)),
+                    Ok(frame) => format!(
+                        "{}\n",
+                        paths.translate(&frame.display(template).to_string())
+                    ),
+                    Err(err) => format!("<invalid panic frame: {err}>\n"),
+                }
+            }
+            (text, None) => paths.translate(text).into_owned(),
+        };
+        output.write_all(report.as_bytes())?;
+        if let (Some(symbols), Some(addresses)) = (symbols, backtrace_addresses(&report)) {
+            symbols.write_backtrace(output, &addresses)?;
+        }
+        buf.clear();
+    }
+    output.flush()
+}
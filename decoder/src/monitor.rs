@@ -0,0 +1,303 @@
+//! Watches serial output (from a device, or stdin) for panics. Other output is passed through, panic reports are
+//! decoded (if printed with the `binary` feature) and highlighted.
+//!
+//! Usage: `panic-serial-monitor [--paths <FIRMWARE|SOURCE_DIR>]... [--elf <FIRMWARE>] [--prefix <PREFIX>]
+//! [--separator <SEPARATOR>] [--fallback <MESSAGE>] [--sentinel <SENTINEL>] [--color <auto|always|never>]
+//! [--exit-on-panic] [--timeout <SECONDS>] [DEVICE]`
+//!
+//! `--paths` and `--elf` translate file names and symbolize backtraces, like with `panic-serial-decode`.
+//! `--prefix`, `--separator`, `--fallback` and `--sentinel` are needed if `Config::PREFIX`, `Config::SEPARATOR`,
+//! `Config::FALLBACK_MESSAGE` or `Config::SENTINEL` were changed.
+//!
+//! The device is read as it is, so set it up beforehand, e.g. with `stty -F /dev/ttyACM0 115200 raw -echo`.
+//!
+//! Meant for hardware-in-the-loop tests as well, the exit code tells what happened:
+//! - 0: the input ended (or `--timeout` passed) without a panic,
+//! - 101: a panic was seen,
+//! - 102: a HardFault was seen,
+//! - 1: something went wrong before a panic, like the device could not be read.
+//!
+//! With `--exit-on-panic`, the monitor stops once a report is complete: when the output goes quiet for a moment,
+//! or the firmware prints something else (e.g. after a reset).
+
+use panic_serial_decoder::{
+    decode, split_line, Classifier, Frame, Kind, PathMap, Symbols, Template,
+};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, IsTerminal, Read, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const USAGE: &str =
+    "usage: panic-serial-monitor [--paths <FIRMWARE|SOURCE_DIR>]... [--elf <FIRMWARE>] \
+     [--prefix <PREFIX>] [--separator <SEPARATOR>] [--fallback <MESSAGE>] [--sentinel <SENTINEL>] \
+     [--color <auto|always|never>] [--exit-on-panic] [--timeout <SECONDS>] [DEVICE]";
+
+/// How long the output has to be quiet for a report to be considered complete.
+const QUIET: Duration = Duration::from_millis(500);
+
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[33m";
+const RESET: &str = "\x1b[0m";
+
+fn main() -> ExitCode {
+    let mut paths = PathMap::new();
+    let mut symbols = None;
+    let mut template = Template::default();
+    let mut color = None;
+    let mut exit_on_panic = false;
+    let mut timeout = None;
+    let mut device = None;
+    let mut args = std::env::args_os().skip(1);
+    while let Some(arg) = args.next() {
+        let flag = arg.to_str().filter(|arg| arg.starts_with("--"));
+        if flag == Some("--exit-on-panic") {
+            exit_on_panic = true;
+            continue;
+        }
+        let Some(flag) = flag else {
+            if device.is_some() {
+                return usage();
+            }
+            device = Some(PathBuf::from(arg));
+            continue;
+        };
+        let Some(value) = args.next() else {
+            return usage();
+        };
+        let path = PathBuf::from(&value);
+        let value = value.to_string_lossy().into_owned();
+        match flag {
+            "--paths" | "--elf" => {
+                if let Err(err) = paths.add_path(&path) {
+                    eprintln!("{}: {err}", path.display());
+                    return ExitCode::FAILURE;
+                }
+                if flag == "--elf" {
+                    match Symbols::load(&path) {
+                        Ok(loaded) => symbols = Some(loaded),
+                        Err(err) => {
+                            eprintln!("{}: {err}", path.display());
+                            return ExitCode::FAILURE;
+                        }
+                    }
+                }
+            }
+            "--prefix" => template.prefix = value,
+            "--separator" => template.separator = value,
+            "--fallback" => template.fallback = value,
+            "--sentinel" => template.sentinel = value,
+            "--color" => {
+                color = match value.as_str() {
+                    "auto" => None,
+                    "always" => Some(true),
+                    "never" => Some(false),
+                    _ => return usage(),
+                }
+            }
+            "--timeout" => match value.parse().map(Duration::try_from_secs_f64) {
+                Ok(Ok(duration)) => timeout = Some(duration),
+                _ => return usage(),
+            },
+            _ => return usage(),
+        }
+    }
+
+    let input: Box<dyn Read + Send> = match device {
+        Some(path) => match File::open(&path) {
+            Ok(file) => Box::new(file),
+            Err(err) => {
+                eprintln!("{}: {err}", path.display());
+                return ExitCode::FAILURE;
+            }
+        },
+        None => Box::new(io::stdin()),
+    };
+
+    let stdout = io::stdout();
+    let color = color.unwrap_or_else(|| stdout.is_terminal());
+    let mut monitor = Monitor {
+        output: stdout.lock(),
+        paths: &paths,
+        symbols: symbols.as_ref(),
+        classifier: Classifier::new(template.clone()),
+        template: &template,
+        color,
+        timestamp: None,
+        seen: None,
+    };
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let result = monitor.run(lines(input), exit_on_panic, deadline);
+    _ = monitor.output.flush();
+    if let Err(err) = &result {
+        eprintln!("{err}");
+    }
+    // A panic is what matters, even if the device went away afterwards.
+    match (monitor.seen, result) {
+        (Some(Kind::HardFault), _) => ExitCode::from(102),
+        (Some(_), _) => ExitCode::from(101),
+        (None, Ok(())) => ExitCode::SUCCESS,
+        (None, Err(_)) => ExitCode::FAILURE,
+    }
+}
+
+fn usage() -> ExitCode {
+    eprintln!("{USAGE}");
+    ExitCode::FAILURE
+}
+
+/// Reads lines from `input` on a thread of their own, so that the monitor can tell when the output goes quiet.
+/// The last message is the result of reading.
+fn lines(input: impl Read + Send + 'static) -> mpsc::Receiver<io::Result<Vec<u8>>> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut input = BufReader::new(input);
+        loop {
+            let mut line = Vec::new();
+            match input.read_until(b'\n', &mut line) {
+                Ok(0) => return,
+                Ok(_) => {
+                    if sender.send(Ok(line)).is_err() {
+                        return;
+                    }
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => {
+                    _ = sender.send(Err(err));
+                    return;
+                }
+            }
+        }
+    });
+    receiver
+}
+
+struct Monitor<'a, W: Write> {
+    output: W,
+    paths: &'a PathMap,
+    symbols: Option<&'a Symbols>,
+    classifier: Classifier,
+    /// How frames are printed.
+    template: &'a Template,
+    color: bool,
+    /// The timestamp of the frame which follows.
+    timestamp: Option<String>,
+    /// The first panic or HardFault seen.
+    seen: Option<Kind>,
+}
+
+impl<W: Write> Monitor<'_, W> {
+    fn run(
+        &mut self,
+        lines: mpsc::Receiver<io::Result<Vec<u8>>>,
+        exit_on_panic: bool,
+        deadline: Option<Instant>,
+    ) -> io::Result<()> {
+        let mut in_report = false;
+        loop {
+            let mut wait =
+                deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            if exit_on_panic && self.seen.is_some() {
+                wait = Some(wait.map_or(QUIET, |wait| wait.min(QUIET)));
+            }
+            let line = match wait {
+                Some(wait) => lines.recv_timeout(wait),
+                None => lines.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            match line {
+                Ok(line) => {
+                    let kind = self.line(&String::from_utf8_lossy(&line?))?;
+                    if exit_on_panic && in_report && kind == Kind::Traffic {
+                        return Ok(());
+                    }
+                    in_report = self.seen.is_some() && kind != Kind::Traffic;
+                }
+                Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+        }
+    }
+
+    /// Prints a line of serial output, returning what it was.
+    fn line(&mut self, line: &str) -> io::Result<Kind> {
+        let (text, frame) = split_line(line);
+        let Some(frame) = frame else {
+            let text = text.trim_end_matches(['\r', '\n']);
+            let kind = self.classifier.classify(text);
+            let text = self.paths.translate(text);
+            self.print(kind, &text)?;
+            if kind == Kind::Detail {
+                self.symbolize(&text)?;
+            }
+            return Ok(kind);
+        };
+
+        if !text.is_empty() {
+            let kind = self.classifier.classify(text);
+            self.print(kind, &self.paths.translate(text))?;
+        }
+        let frame = match decode(frame) {
+            Ok(frame) => frame,
+            Err(err) => {
+                let text = format!("<invalid panic frame: {err}>");
+                self.print(Kind::Detail, &text)?;
+                return Ok(Kind::Detail);
+            }
+        };
+        let kind = self.classifier.classify_frame(&frame);
+        if let Frame::Timestamp(_) = frame {
+            // Like in text reports, the timestamp goes in front of what follows.
+            self.timestamp = Some(frame.display(self.template).to_string());
+            return Ok(kind);
+        }
+        let text = match self.timestamp.take() {
+            Some(timestamp) => format!("{timestamp} {}", frame.display(self.template)),
+            None => frame.display(self.template).to_string(),
+        };
+        let text = self.paths.translate(&text).into_owned();
+        self.print(kind, &text)?;
+        if let Frame::Backtrace(addresses) = &frame {
+            self.write_backtrace(addresses)?;
+        }
+        Ok(kind)
+    }
+
+    fn print(&mut self, kind: Kind, text: &str) -> io::Result<()> {
+        if matches!(kind, Kind::Panic | Kind::HardFault) && self.seen.is_none() {
+            self.seen = Some(kind);
+        }
+        let color = match kind {
+            Kind::Traffic => None,
+            Kind::Panic | Kind::HardFault => Some(RED),
+            Kind::Detail => Some(YELLOW),
+        };
+        match color.filter(|_| self.color) {
+            Some(color) => writeln!(self.output, "{color}{text}{RESET}"),
+            None => writeln!(self.output, "{text}"),
+        }
+    }
+
+    /// Symbolizes the line, if it is a backtrace.
+    fn symbolize(&mut self, text: &str) -> io::Result<()> {
+        match panic_serial_decoder::backtrace_addresses(text) {
+            Some(addresses) => self.write_backtrace(&addresses),
+            None => Ok(()),
+        }
+    }
+
+    fn write_backtrace(&mut self, addresses: &[u32]) -> io::Result<()> {
+        let Some(symbols) = self.symbols else {
+            return Ok(());
+        };
+        if self.color {
+            write!(self.output, "{YELLOW}")?;
+        }
+        symbols.write_backtrace(&mut self.output, addresses)?;
+        if self.color {
+            write!(self.output, "{RESET}")?;
+        }
+        Ok(())
+    }
+}
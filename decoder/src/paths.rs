@@ -0,0 +1,177 @@
+//! Translating file hashes back to file names.
+
+use panic_serial::frame::file_hash;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// How far back from a `.rs` suffix to look for the start of a file name in a firmware image.
+const MAX_PATH_LEN: usize = 256;
+
+/// Maps file hashes (see [`panic_serial::frame::file_hash`]) to the file names they were computed from.
+#[derive(Clone, Debug, Default)]
+pub struct PathMap(HashMap<u32, String>);
+
+impl PathMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a single file name.
+    pub fn insert(&mut self, file: &str) {
+        self.0.insert(file_hash(file), file.to_owned());
+    }
+
+    /// Returns the file name with the given hash, if it is known.
+    pub fn get(&self, hash: u32) -> Option<&str> {
+        self.0.get(&hash).map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Adds the file names found in a firmware image (ELF, or any other format).
+    ///
+    /// The file names of panic locations are stored as plain strings, but without any separator to neighbouring data.
+    /// So for every `.rs` found, every string of printable characters ending there is added, and the hash
+    /// of the complete file name will be among them.
+    pub fn add_firmware(&mut self, image: &[u8]) {
+        let mut end = 0;
+        while let Some(pos) = find(&image[end..], b".rs") {
+            end += pos + 3;
+            let mut start = end - 3;
+            while start > 0 && end - start < MAX_PATH_LEN && is_path_char(image[start - 1]) {
+                start -= 1;
+                if let Ok(file) = std::str::from_utf8(&image[start..end]) {
+                    self.insert(file);
+                }
+            }
+        }
+    }
+
+    /// Adds all `.rs` files below `dir`, with paths relative to `dir` (like `src/main.rs`, when `dir` is the
+    /// crate root), as well as their absolute paths (which is how dependencies' files are named).
+    pub fn add_source_dir(&mut self, dir: &Path) -> io::Result<()> {
+        let root = dir.canonicalize()?;
+        self.add_source_dir_inner(&root, &root)
+    }
+
+    fn add_source_dir_inner(&mut self, root: &Path, dir: &Path) -> io::Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                if path
+                    .file_name()
+                    .is_some_and(|name| name == "target" || name == ".git")
+                {
+                    continue;
+                }
+                self.add_source_dir_inner(root, &path)?;
+            } else if path.extension().is_some_and(|ext| ext == "rs") {
+                if let Some(file) = path.to_str() {
+                    self.insert(file);
+                }
+                if let Some(file) = path.strip_prefix(root).ok().and_then(Path::to_str) {
+                    self.insert(file);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds the file names from a firmware image (if `path` is a file) or a source tree (if it is a directory).
+    pub fn add_path(&mut self, path: &Path) -> io::Result<()> {
+        if path.is_dir() {
+            self.add_source_dir(path)
+        } else {
+            self.add_firmware(&fs::read(path)?);
+            Ok(())
+        }
+    }
+
+    /// Replaces hashed file names in a text report (`Panic at #1660407c:91:9`) with the known file names.
+    ///
+    /// Any `#` followed by 8 hex digits and `:<line>` is taken for a hashed file name, whatever `Config::PREFIX`
+    /// comes before it.
+    pub fn translate<'a>(&self, line: &'a str) -> Cow<'a, str> {
+        let mut translated = String::new();
+        let mut done = 0;
+        for (pos, _) in line.match_indices('#') {
+            let Some(file) = hashed_file(&line[pos + 1..]).and_then(|hash| self.get(hash)) else {
+                continue;
+            };
+            translated.push_str(&line[done..pos]);
+            translated.push_str(file);
+            done = pos + 9;
+        }
+        if done == 0 {
+            return Cow::Borrowed(line);
+        }
+        translated.push_str(&line[done..]);
+        Cow::Owned(translated)
+    }
+}
+
+/// Returns the hash at the start of `text`, if it is 8 hex digits followed by `:` and a line number.
+fn hashed_file(text: &str) -> Option<u32> {
+    let hex = text.get(..8)?;
+    let line = text[8..].strip_prefix(':')?;
+    if !hex.bytes().all(|c| c.is_ascii_hexdigit())
+        || !line.bytes().next().is_some_and(|c| c.is_ascii_digit())
+    {
+        return None;
+    }
+    u32::from_str_radix(hex, 16).ok()
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn is_path_char(c: u8) -> bool {
+    c.is_ascii_graphic() && c != b'"' || c == b' '
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_file_names_in_firmware() {
+        let mut paths = PathMap::new();
+        paths.add_firmware(b"\x00\x7fcalled `Option::unwrap()`src/main.rsattempt to add\x00/home/me/lib/src/lib.rs\xff");
+        assert_eq!(paths.get(file_hash("src/main.rs")), Some("src/main.rs"));
+        assert_eq!(
+            paths.get(file_hash("/home/me/lib/src/lib.rs")),
+            Some("/home/me/lib/src/lib.rs")
+        );
+    }
+
+    #[test]
+    fn translates_text_report() {
+        let mut paths = PathMap::new();
+        paths.insert("src/main.rs");
+        assert_eq!(
+            paths.translate("Panic at #1660407c:91:9: attempt to subtract with overflow\r\n"),
+            "Panic at src/main.rs:91:9: attempt to subtract with overflow\r\n"
+        );
+        assert_eq!(
+            paths.translate("Panic at #00000000:1:1\r\n"),
+            "Panic at #00000000:1:1\r\n"
+        );
+        assert_eq!(paths.translate("Panic at #1660407c"), "Panic at #1660407c");
+        assert_eq!(
+            paths.translate("[12] panicked at #1660407c:91:9 (#1)"),
+            "[12] panicked at src/main.rs:91:9 (#1)"
+        );
+    }
+}
@@ -0,0 +1,66 @@
+//! Symbolizing the addresses of backtraces against the firmware ELF.
+
+use addr2line::Loader;
+use std::error::Error;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Debug information of a firmware image.
+pub struct Symbols(Loader);
+
+impl Symbols {
+    /// Loads the debug information (or at least the symbol table) of an ELF file.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Loader::new(path).map(Symbols)
+    }
+
+    /// Describes the call leading to a return address: the calling function with its location, preceded by
+    /// the functions inlined into it. Empty if nothing is known about the address.
+    pub fn describe(&self, address: u32) -> Vec<String> {
+        // Return addresses point behind the call (and have the Thumb bit set on Cortex-M).
+        let probe = u64::from(address & !1).saturating_sub(1);
+        let mut calls = Vec::new();
+        if let Ok(mut frames) = self.0.find_frames(probe) {
+            while let Ok(Some(frame)) = frames.next() {
+                let function = frame
+                    .function
+                    .as_ref()
+                    .and_then(|function| function.demangle().ok())
+                    .map_or_else(|| "??".to_owned(), |name| name.into_owned());
+                let location = frame
+                    .location
+                    .and_then(|l| Some((l.file?, l.line?, l.column)));
+                calls.push(match location {
+                    Some((file, line, Some(column))) if column > 0 => {
+                        format!("{function} at {file}:{line}:{column}")
+                    }
+                    Some((file, line, _)) => format!("{function} at {file}:{line}"),
+                    None => function,
+                });
+            }
+        }
+        if calls.is_empty() {
+            if let Some(symbol) = self.0.find_symbol(probe) {
+                calls.push(addr2line::demangle_auto(symbol.into(), None).into_owned());
+            }
+        }
+        calls
+    }
+
+    /// Writes one line per address of a backtrace, followed by the calls it belongs to.
+    pub fn write_backtrace(&self, output: &mut impl Write, addresses: &[u32]) -> io::Result<()> {
+        for (i, address) in addresses.iter().enumerate() {
+            let calls = self.describe(*address);
+            let mut calls = calls.iter();
+            writeln!(
+                output,
+                "{i:>4}: 0x{address:08x} {}",
+                calls.next().map_or("??", String::as_str)
+            )?;
+            for call in calls {
+                writeln!(output, "{:>17} {call}", "")?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,135 @@
+//! Runs `panic-serial-monitor` on a pseudo terminal, as it would be run on a serial device.
+#![cfg(target_os = "linux")]
+
+use std::ffi::CStr;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::process::{Command, Output, Stdio};
+
+/// Opens a pseudo terminal in raw mode, returning its two ends: the master, which plays the device, and the slave
+/// along with its path, which the monitor reads from.
+fn open_pty() -> (File, File, String) {
+    // SAFETY: plain calls into libc, checking their results.
+    unsafe {
+        let master = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+        assert!(master >= 0, "posix_openpt failed");
+        let master = File::from_raw_fd(master);
+        assert_eq!(libc::grantpt(master.as_raw_fd()), 0);
+        assert_eq!(libc::unlockpt(master.as_raw_fd()), 0);
+        let mut name = [0; 64];
+        assert_eq!(
+            libc::ptsname_r(master.as_raw_fd(), name.as_mut_ptr(), name.len()),
+            0
+        );
+        let path = CStr::from_ptr(name.as_ptr()).to_str().unwrap().to_owned();
+
+        // Keeps line endings as they are, like `stty raw -echo`.
+        let slave = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+        let mut termios = std::mem::zeroed();
+        assert_eq!(libc::tcgetattr(slave.as_raw_fd(), &mut termios), 0);
+        libc::cfmakeraw(&mut termios);
+        assert_eq!(
+            libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &termios),
+            0
+        );
+        (master, slave, path)
+    }
+}
+
+/// Sends `output` through a pseudo terminal to the monitor, returning what it printed.
+fn monitor(output: &str, args: &[&str]) -> Output {
+    let (mut master, _slave, path) = open_pty();
+    master.write_all(output.as_bytes()).unwrap();
+    Command::new(env!("CARGO_BIN_EXE_panic-serial-monitor"))
+        .args(["--color", "never"])
+        .args(args)
+        .arg(path)
+        .stdout(Stdio::piped())
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn exits_after_panic() {
+    let output = monitor(
+        "booting\r\n\
+         temperature: 21\r\n\
+         !!! PANIC\r\n\
+         Panic at src/main.rs:91:9: attempt to subtract with overflow\r\n\
+         \x01AzUSAAgBDwAIbLU\r\n",
+        &["--exit-on-panic"],
+    );
+    assert_eq!(output.status.code(), Some(101));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "booting\n\
+         temperature: 21\n\
+         !!! PANIC\n\
+         Panic at src/main.rs:91:9: attempt to subtract with overflow\n\
+         Backtrace: 0x08001235 0x08000f01\n"
+    );
+}
+
+#[test]
+fn exits_after_hard_fault() {
+    let output = monitor(
+        "\x01AgAAAAABAAAAAgAAAAMAAAAMAAAAAQ8ACDQSAAgAAABhAIIAAAAAAEAAAAAAAIAAICNv\r\n",
+        &["--exit-on-panic"],
+    );
+    assert_eq!(output.status.code(), Some(102));
+}
+
+#[test]
+fn passes_traffic_through() {
+    let output = monitor("booting\r\nPANIC !?\r\n", &["--timeout", "0.5"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "booting\nPANIC !?\n"
+    );
+}
+
+#[test]
+fn follows_configured_template() {
+    let output = monitor(
+        "booting\r\n\
+         Firmware - blinky 0.3.1\r\n\
+         panicked at src/main.rs:91:9 - attempt to subtract with overflow\r\n\
+         Breadcrumb - entering state 3\r\n",
+        &[
+            "--prefix",
+            "panicked at ",
+            "--separator",
+            " - ",
+            "--fallback",
+            "oops",
+            "--exit-on-panic",
+        ],
+    );
+    assert_eq!(output.status.code(), Some(101));
+
+    let output = monitor(
+        "booting\r\noops\r\n",
+        &["--fallback", "oops", "--exit-on-panic"],
+    );
+    assert_eq!(output.status.code(), Some(101));
+
+    let output = monitor(
+        "booting\r\nHardFault - r0=0x00000000\r\n",
+        &["--separator", " - ", "--exit-on-panic"],
+    );
+    assert_eq!(output.status.code(), Some(102));
+}
+
+#[test]
+fn rejects_invalid_timeout() {
+    for timeout in ["-1", "NaN", "inf"] {
+        let output = monitor("booting\r\n", &["--timeout", timeout]);
+        assert_eq!(output.status.code(), Some(1), "--timeout {timeout}");
+    }
+}
@@ -0,0 +1,324 @@
+//! Adapters for ports implementing the `embedded-hal-nb` or `embedded-io` traits, instead of `ufmt::uWrite`.
+//!
+//! Wrap the port type in the adapter when invoking the macro, and the port when sharing it:
+//! ```ignore
+//! panic_serial::impl_panic_handler!(panic_serial::EmbeddedIo<MyUart>);
+//!
+//! let serial = share_serial_port_with_panic(panic_serial::EmbeddedIo(uart)).unwrap();
+//! ```
+//! The adapters dereference to the wrapped port, so it can be used as before.
+
+use core::ops::{Deref, DerefMut};
+use ufmt::uWrite;
+
+/// Adapts a port implementing `embedded_hal_nb::serial::Write<u8>`.
+///
+/// Writes block until each byte is accepted, `flush` blocks until the port is done transmitting.
+#[cfg(feature = "embedded-hal-nb")]
+pub struct EmbeddedHalNb<T>(pub T);
+
+#[cfg(feature = "embedded-hal-nb")]
+impl<T: embedded_hal_nb::serial::Write<u8>> uWrite for EmbeddedHalNb<T> {
+    type Error = T::Error;
+
+    fn write_str(&mut self, s: &str) -> Result<(), T::Error> {
+        for byte in s.bytes() {
+            embedded_hal_nb::nb::block!(self.0.write(byte))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "embedded-hal-nb")]
+impl<T: embedded_hal_nb::serial::Write<u8>> EmbeddedHalNb<T> {
+    /// Blocks until all written bytes are transmitted.
+    pub fn flush(&mut self) -> Result<(), T::Error> {
+        embedded_hal_nb::nb::block!(self.0.flush())
+    }
+}
+
+/// Adapts a port implementing `embedded_hal_nb::serial::Write<u8>`, like [`EmbeddedHalNb`], but gives up
+/// when the port is stuck.
+///
+/// Every byte, and the `flush`, may take up to `POLLS` polls of the port; after that the write fails with
+/// [`TimeoutError::TimedOut`]. From then on, every write and `flush` fails at once, so the panic handler spends
+/// at most `POLLS` polls on a stuck port (rather than that much on every part of the report), and gets to
+/// [`Config::after_panic`](crate::Config::after_panic). Outside of the panic handler, [`reset`](Self::reset)
+/// tries the port again. How long a poll takes depends on the port and the clock, so the bound is best found by trying.
+#[cfg(feature = "embedded-hal-nb")]
+pub struct EmbeddedHalNbTimeout<T, const POLLS: u32> {
+    port: T,
+    timed_out: bool,
+}
+
+/// Error of [`EmbeddedHalNbTimeout`].
+#[cfg(feature = "embedded-hal-nb")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimeoutError<E> {
+    /// The port reported an error.
+    Port(E),
+    /// The port was not ready within the given number of polls (now or before).
+    TimedOut,
+}
+
+#[cfg(feature = "embedded-hal-nb")]
+impl<T: embedded_hal_nb::serial::Write<u8>, const POLLS: u32> uWrite
+    for EmbeddedHalNbTimeout<T, POLLS>
+{
+    type Error = TimeoutError<T::Error>;
+
+    fn write_str(&mut self, s: &str) -> Result<(), Self::Error> {
+        for byte in s.bytes() {
+            self.poll(|port| port.write(byte))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "embedded-hal-nb")]
+impl<T, const POLLS: u32> EmbeddedHalNbTimeout<T, POLLS> {
+    /// Wraps `port`, giving up on it after `POLLS` polls.
+    ///
+    /// Only `embedded-hal-nb` ports can be time-limited like this: they report when they would block, which plain
+    /// `uWrite` and `embedded-io` ports do not.
+    pub const fn new(port: T) -> Self {
+        EmbeddedHalNbTimeout {
+            port,
+            timed_out: false,
+        }
+    }
+
+    /// Returns the wrapped port.
+    pub fn into_inner(self) -> T {
+        self.port
+    }
+
+    /// Whether the port timed out, so that writes fail at once.
+    pub fn timed_out(&self) -> bool {
+        self.timed_out
+    }
+
+    /// Lets writes poll the port again after it timed out.
+    pub fn reset(&mut self) {
+        self.timed_out = false;
+    }
+}
+
+#[cfg(feature = "embedded-hal-nb")]
+impl<T: embedded_hal_nb::serial::Write<u8>, const POLLS: u32> EmbeddedHalNbTimeout<T, POLLS> {
+    /// Blocks until all written bytes are transmitted, or the port is stuck.
+    pub fn flush(&mut self) -> Result<(), TimeoutError<T::Error>> {
+        self.poll(|port| port.flush())
+    }
+
+    /// Calls `f` until it is done, at most `POLLS` times, unless the port already timed out.
+    fn poll(
+        &mut self,
+        mut f: impl FnMut(&mut T) -> embedded_hal_nb::nb::Result<(), T::Error>,
+    ) -> Result<(), TimeoutError<T::Error>> {
+        if self.timed_out {
+            return Err(TimeoutError::TimedOut);
+        }
+        for _ in 0..POLLS {
+            match f(&mut self.port) {
+                Ok(()) => return Ok(()),
+                Err(embedded_hal_nb::nb::Error::Other(err)) => return Err(TimeoutError::Port(err)),
+                Err(embedded_hal_nb::nb::Error::WouldBlock) => {}
+            }
+        }
+        self.timed_out = true;
+        Err(TimeoutError::TimedOut)
+    }
+}
+
+/// Adapts a port implementing `embedded_io::Write`.
+///
+/// Writes block until all bytes are accepted, `flush` is the port's own `flush`.
+#[cfg(feature = "embedded-io")]
+pub struct EmbeddedIo<T>(pub T);
+
+#[cfg(feature = "embedded-io")]
+impl<T: embedded_io::Write> uWrite for EmbeddedIo<T> {
+    type Error = T::Error;
+
+    fn write_str(&mut self, s: &str) -> Result<(), T::Error> {
+        let mut buf = s.as_bytes();
+        while !buf.is_empty() {
+            match self.0.write(buf)? {
+                // `write_all` would panic here, which is not helpful within the panic handler.
+                0 => break,
+                n => buf = &buf[n..],
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "embedded-io")]
+impl<T: embedded_io::Write> EmbeddedIo<T> {
+    /// Blocks until all written bytes are transmitted.
+    pub fn flush(&mut self) -> Result<(), T::Error> {
+        self.0.flush()
+    }
+}
+
+macro_rules! impl_deref {
+    ($adapter:ident $(, const $param:ident: $param_ty:ty)? => $field:tt) => {
+        impl<T $(, const $param: $param_ty)?> Deref for $adapter<T $(, $param)?> {
+            type Target = T;
+
+            fn deref(&self) -> &T {
+                &self.$field
+            }
+        }
+
+        impl<T $(, const $param: $param_ty)?> DerefMut for $adapter<T $(, $param)?> {
+            fn deref_mut(&mut self) -> &mut T {
+                &mut self.$field
+            }
+        }
+    };
+}
+
+#[cfg(feature = "embedded-hal-nb")]
+impl_deref!(EmbeddedHalNb => 0);
+#[cfg(feature = "embedded-hal-nb")]
+impl_deref!(EmbeddedHalNbTimeout, const POLLS: u32 => port);
+#[cfg(feature = "embedded-io")]
+impl_deref!(EmbeddedIo => 0);
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::vec::Vec;
+
+    #[test]
+    #[cfg(feature = "embedded-hal-nb")]
+    fn embedded_hal_nb_retries_until_accepted() {
+        use embedded_hal_nb::nb;
+        use embedded_hal_nb::serial::{ErrorKind, ErrorType, Write};
+
+        struct Port {
+            sent: Vec<u8>,
+            busy: bool,
+        }
+
+        impl ErrorType for Port {
+            type Error = ErrorKind;
+        }
+
+        impl Write for Port {
+            fn write(&mut self, word: u8) -> nb::Result<(), ErrorKind> {
+                self.busy = !self.busy;
+                if self.busy {
+                    return Err(nb::Error::WouldBlock);
+                }
+                self.sent.push(word);
+                Ok(())
+            }
+
+            fn flush(&mut self) -> nb::Result<(), ErrorKind> {
+                Ok(())
+            }
+        }
+
+        let mut port = EmbeddedHalNb(Port {
+            sent: Vec::new(),
+            busy: false,
+        });
+        ufmt::uwrite!(port, "Panic at {}:{}", "src/main.rs", 91u32).unwrap();
+        port.flush().unwrap();
+        assert_eq!(port.sent, b"Panic at src/main.rs:91");
+    }
+
+    #[test]
+    #[cfg(feature = "embedded-hal-nb")]
+    fn embedded_hal_nb_timeout_gives_up() {
+        use embedded_hal_nb::nb;
+        use embedded_hal_nb::serial::{ErrorKind, ErrorType, Write};
+
+        // Accepts `ready` bytes, then stalls.
+        struct Port {
+            sent: Vec<u8>,
+            ready: usize,
+            polls: u32,
+        }
+
+        impl ErrorType for Port {
+            type Error = ErrorKind;
+        }
+
+        impl Write for Port {
+            fn write(&mut self, word: u8) -> nb::Result<(), ErrorKind> {
+                self.polls += 1;
+                if self.sent.len() == self.ready {
+                    return Err(nb::Error::WouldBlock);
+                }
+                self.sent.push(word);
+                Ok(())
+            }
+
+            fn flush(&mut self) -> nb::Result<(), ErrorKind> {
+                self.polls += 1;
+                Err(nb::Error::WouldBlock)
+            }
+        }
+
+        let mut port = EmbeddedHalNbTimeout::<_, 10>::new(Port {
+            sent: Vec::new(),
+            ready: 5,
+            polls: 0,
+        });
+        assert_eq!(
+            ufmt::uwrite!(port, "Panic at {}", "src/main.rs"),
+            Err(TimeoutError::TimedOut)
+        );
+        assert_eq!(port.sent, b"Panic");
+        assert_eq!(port.polls, 5 + 10);
+        assert!(port.timed_out());
+
+        // Later parts of the report do not wait again.
+        assert_eq!(
+            ufmt::uwrite!(port, "{}", 91u32),
+            Err(TimeoutError::TimedOut)
+        );
+        assert_eq!(port.flush(), Err(TimeoutError::TimedOut));
+        assert_eq!(port.polls, 5 + 10);
+
+        port.reset();
+        assert_eq!(port.flush(), Err(TimeoutError::TimedOut));
+        assert_eq!(port.polls, 5 + 10 + 10);
+    }
+
+    #[test]
+    #[cfg(feature = "embedded-io")]
+    fn embedded_io_writes_everything() {
+        use embedded_io::{ErrorKind, ErrorType, Write};
+
+        // Accepts at most two bytes per call.
+        struct Port(Vec<u8>);
+
+        impl ErrorType for Port {
+            type Error = ErrorKind;
+        }
+
+        impl Write for Port {
+            fn write(&mut self, buf: &[u8]) -> Result<usize, ErrorKind> {
+                let n = buf.len().min(2);
+                self.0.extend_from_slice(&buf[..n]);
+                Ok(n)
+            }
+
+            fn flush(&mut self) -> Result<(), ErrorKind> {
+                Ok(())
+            }
+        }
+
+        let mut port = EmbeddedIo(Port(Vec::new()));
+        ufmt::uwrite!(port, "Panic at {}:{}", "src/main.rs", 91u32).unwrap();
+        port.flush().unwrap();
+        assert_eq!(port.0 .0, b"Panic at src/main.rs:91");
+    }
+}
@@ -0,0 +1,97 @@
+//! Printing a backtrace of raw return addresses, with the `backtrace` feature.
+//!
+//! There is no unwinding information on the device, so the stack is scanned for words which point into
+//! the code (see [`Config::code_range`]). Most of these are return addresses, some may be stale values
+//! or function pointers. The `panic-serial-decode` tool symbolizes them with `--elf`.
+
+use crate::frame::{self, Hex};
+use crate::Config;
+use core::ops::Range;
+use ufmt::uWrite;
+
+/// Prints the backtrace, scanning the stack from the current stack pointer up to [`Config::stack_start`].
+#[cfg_attr(test, allow(dead_code))]
+pub(crate) fn print_backtrace<C: Config, W: uWrite + ?Sized>(w: &mut W) {
+    scan::<C>(|addresses| print_addresses::<C, W>(w, addresses));
+}
+
+/// Passes the first [`Config::BACKTRACE_DEPTH`] return addresses found on the stack to `f`, unless there is no
+/// [`Config::code_range`] to look for them in.
+#[inline(never)]
+#[cfg_attr(test, allow(dead_code))]
+pub(crate) fn scan<C: Config>(f: impl FnOnce(&mut dyn Iterator<Item = usize>)) {
+    let code = C::code_range();
+    if code.is_empty() {
+        return;
+    }
+    let marker = 0usize;
+    let sp = core::ptr::addr_of!(marker) as usize;
+    let words = (sp..C::stack_start())
+        .step_by(core::mem::size_of::<usize>())
+        // SAFETY: everything between the stack pointer and the start of the stack is part of the stack.
+        .map(|address| unsafe { core::ptr::read_volatile(address as *const usize) });
+    f(&mut return_addresses(words, code).take(C::BACKTRACE_DEPTH));
+}
+
+/// Filters the words from the stack which look like return addresses.
+pub(crate) fn return_addresses(
+    words: impl Iterator<Item = usize>,
+    code: Range<usize>,
+) -> impl Iterator<Item = usize> {
+    // On Thumb targets, return addresses have the lowest bit set.
+    let thumb = cfg!(target_arch = "arm");
+    words.filter(move |word| code.contains(word) && (!thumb || word & 1 == 1))
+}
+
+pub(crate) fn print_addresses<C: Config, W: uWrite + ?Sized>(
+    w: &mut W,
+    addresses: impl Iterator<Item = usize>,
+) {
+    if cfg!(feature = "binary") {
+        frame::write_backtrace(w, addresses.map(|address| address as u32), C::LINE_ENDING);
+        return;
+    }
+
+    _ = w.write_str("Backtrace");
+    let mut separator = C::SEPARATOR;
+    for address in addresses {
+        _ = ufmt::uwrite!(w, "{}0x{}", separator, Hex(address as u32));
+        separator = " ";
+    }
+    _ = w.write_str(C::LINE_ENDING);
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::vec::Vec;
+
+    #[test]
+    fn finds_return_addresses() {
+        let stack = [
+            0x2000_0100,
+            0x0800_1235,
+            7,
+            0x0800_0f01,
+            0x0810_0000,
+            0x0800_0000,
+        ];
+        let addresses: Vec<_> =
+            return_addresses(stack.into_iter(), 0x0800_0000..0x0801_0000).collect();
+        assert_eq!(addresses, [0x0800_1235, 0x0800_0f01, 0x0800_0000]);
+    }
+
+    #[test]
+    #[cfg(not(feature = "binary"))]
+    fn prints_addresses() {
+        use crate::tests::Buffer;
+        use crate::DefaultConfig;
+        use std::string::String;
+
+        let mut buffer = Buffer(String::new());
+        print_addresses::<DefaultConfig, _>(&mut buffer, [0x0800_1235, 0x0800_0f01].into_iter());
+        assert_eq!(buffer.0, "Backtrace: 0x08001235 0x08000f01\r\n");
+    }
+}
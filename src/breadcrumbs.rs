@@ -0,0 +1,291 @@
+//! A log of what the firmware did last, printed on a panic (with the `breadcrumbs` feature).
+//!
+//! Without the feature, only [`Entry`] is used, so that `breadcrumb!` still type checks its arguments.
+
+#![cfg_attr(not(feature = "breadcrumbs"), allow(dead_code))]
+
+use crate::frame;
+use crate::record::Truncate;
+use crate::Config;
+use core::cell::UnsafeCell;
+use core::convert::Infallible;
+use core::fmt::Write;
+use portable_atomic::{AtomicBool, AtomicUsize, Ordering};
+use ufmt::uWrite;
+
+/// Number of breadcrumbs kept. Older ones are overwritten.
+pub const BREADCRUMB_COUNT: usize = 8;
+
+/// Maximum number of bytes kept of a breadcrumb. Longer ones are cut off.
+pub const BREADCRUMB_CAPACITY: usize = 32;
+
+struct Slot {
+    /// Set while the slot is written or read, so that no one else gets to it in the meantime.
+    busy: AtomicBool,
+    /// Number of the breadcrumb in the slot, plus one. `0` while it is being written.
+    stamp: AtomicUsize,
+    entry: UnsafeCell<(usize, [u8; BREADCRUMB_CAPACITY])>,
+}
+
+impl Slot {
+    /// Claims the slot, unless someone else has it (e.g. the code an interrupt interrupted).
+    fn claim(&self) -> bool {
+        self.busy
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    fn release(&self) {
+        self.busy.store(false, Ordering::Release);
+    }
+}
+
+/// Ring buffer of the last [`BREADCRUMB_COUNT`] breadcrumbs.
+pub(crate) struct Breadcrumbs {
+    next: AtomicUsize,
+    slots: [Slot; BREADCRUMB_COUNT],
+}
+
+// Each slot is only accessed by the one who claimed it, and only read if its stamp is valid.
+unsafe impl Sync for Breadcrumbs {}
+
+impl Breadcrumbs {
+    pub(crate) const fn new() -> Self {
+        // Only used to initialize the array, every slot is a copy.
+        #[allow(clippy::declare_interior_mutable_const)]
+        const SLOT: Slot = Slot {
+            busy: AtomicBool::new(false),
+            stamp: AtomicUsize::new(0),
+            entry: UnsafeCell::new((0, [0; BREADCRUMB_CAPACITY])),
+        };
+        Breadcrumbs {
+            next: AtomicUsize::new(0),
+            slots: [SLOT; BREADCRUMB_COUNT],
+        }
+    }
+
+    /// Adds a breadcrumb, written by `f`.
+    ///
+    /// The breadcrumb is dropped if its slot is still in use, which happens when an interrupt adds
+    /// [`BREADCRUMB_COUNT`] breadcrumbs while the code it interrupted is adding one.
+    pub(crate) fn record(&self, f: impl FnOnce(&mut Entry<'_>)) {
+        let number = self.next.fetch_add(1, Ordering::Relaxed);
+        let slot = &self.slots[number % BREADCRUMB_COUNT];
+        if !slot.claim() {
+            return;
+        }
+        // Invalidates the slot before touching it, so that a panic in between does not print half of it.
+        slot.stamp.store(0, Ordering::Relaxed);
+        // SAFETY: the slot is claimed.
+        let (len, bytes) = unsafe { &mut *slot.entry.get() };
+        let mut entry = Entry(Truncate::new(bytes));
+        f(&mut entry);
+        *len = entry.0.len;
+        slot.stamp.store(number.wrapping_add(1), Ordering::Relaxed);
+        slot.release();
+    }
+
+    /// Calls `f` with every complete breadcrumb, oldest first.
+    pub(crate) fn for_each(&self, mut f: impl FnMut(&str)) {
+        let next = self.next.load(Ordering::Acquire);
+        for age in (1..=BREADCRUMB_COUNT).rev() {
+            let number = next.wrapping_sub(age);
+            let slot = &self.slots[number % BREADCRUMB_COUNT];
+            if !slot.claim() {
+                continue;
+            }
+            if slot.stamp.load(Ordering::Relaxed) == number.wrapping_add(1) {
+                // SAFETY: the slot is claimed, and the stamp says it holds a complete breadcrumb.
+                let (len, bytes) = unsafe { &*slot.entry.get() };
+                if let Ok(breadcrumb) = core::str::from_utf8(&bytes[..*len]) {
+                    f(breadcrumb);
+                }
+            }
+            slot.release();
+        }
+    }
+}
+
+/// Prints every breadcrumb on a line of its own, oldest first.
+pub(crate) fn print_breadcrumbs<C: Config, W: uWrite + ?Sized>(
+    w: &mut W,
+    breadcrumbs: &Breadcrumbs,
+) {
+    breadcrumbs.for_each(|breadcrumb| {
+        if cfg!(feature = "binary") {
+            frame::write_breadcrumb(w, breadcrumb, C::LINE_ENDING);
+        } else {
+            _ = ufmt::uwrite!(
+                w,
+                "Breadcrumb{}{}{}",
+                C::SEPARATOR,
+                breadcrumb,
+                C::LINE_ENDING
+            );
+        }
+    });
+}
+
+/// Where `breadcrumb!` writes to.
+#[doc(hidden)]
+pub struct Entry<'a>(Truncate<'a>);
+
+impl uWrite for Entry<'_> {
+    type Error = Infallible;
+
+    fn write_str(&mut self, s: &str) -> Result<(), Infallible> {
+        _ = self.0.write_str(s);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "breadcrumbs")]
+pub(crate) static BREADCRUMBS: Breadcrumbs = Breadcrumbs::new();
+
+/// Called internally by `breadcrumb!`.
+#[cfg(feature = "breadcrumbs")]
+pub fn _record_breadcrumb(f: impl FnOnce(&mut Entry<'_>)) {
+    BREADCRUMBS.record(f);
+}
+
+/// Adds a breadcrumb: a short note of what the firmware is doing, formatted like `ufmt::uwrite!`.
+///
+/// The last [`BREADCRUMB_COUNT`] breadcrumbs are printed after the panic message (with the `breadcrumbs`
+/// feature, otherwise this does nothing):
+/// ```ignore
+/// panic_serial::breadcrumb!("entering state {}", state);
+/// ```
+#[cfg(feature = "breadcrumbs")]
+#[macro_export]
+macro_rules! breadcrumb {
+    ($($arg:tt)*) => {
+        $crate::_record_breadcrumb(|entry| {
+            // `uwrite!` refers to `ufmt`, which the calling crate does not need to depend on.
+            use $crate::_ufmt as ufmt;
+            _ = ufmt::uwrite!(entry, $($arg)*);
+        })
+    };
+}
+
+/// Adds a breadcrumb: a short note of what the firmware is doing, formatted like `ufmt::uwrite!`.
+///
+/// The last [`BREADCRUMB_COUNT`] breadcrumbs are printed after the panic message (with the `breadcrumbs`
+/// feature, otherwise this does nothing):
+/// ```ignore
+/// panic_serial::breadcrumb!("entering state {}", state);
+/// ```
+#[cfg(not(feature = "breadcrumbs"))]
+#[macro_export]
+macro_rules! breadcrumb {
+    ($($arg:tt)*) => {
+        // Still type checks the arguments, and uses them.
+        if false {
+            let _ = |entry: &mut $crate::_BreadcrumbEntry<'_>| {
+                use $crate::_ufmt as ufmt;
+                _ = ufmt::uwrite!(entry, $($arg)*);
+            };
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::format;
+    use std::string::{String, ToString};
+    use std::vec::Vec;
+
+    fn collect(breadcrumbs: &Breadcrumbs) -> Vec<String> {
+        let mut collected = Vec::new();
+        breadcrumbs.for_each(|breadcrumb| collected.push(breadcrumb.to_string()));
+        collected
+    }
+
+    fn record(breadcrumbs: &Breadcrumbs, n: usize) {
+        breadcrumbs.record(|entry| {
+            _ = ufmt::uwrite!(entry, "entering state {}", n);
+        });
+    }
+
+    #[test]
+    fn keeps_the_last_breadcrumbs() {
+        let breadcrumbs = Breadcrumbs::new();
+        assert!(collect(&breadcrumbs).is_empty());
+
+        record(&breadcrumbs, 1);
+        record(&breadcrumbs, 2);
+        assert_eq!(
+            collect(&breadcrumbs),
+            ["entering state 1", "entering state 2"]
+        );
+
+        for n in 3..=BREADCRUMB_COUNT + 3 {
+            record(&breadcrumbs, n);
+        }
+        let expected: Vec<_> = (4..=BREADCRUMB_COUNT + 3)
+            .map(|n| format!("entering state {n}"))
+            .collect();
+        assert_eq!(collect(&breadcrumbs), expected);
+    }
+
+    #[test]
+    fn cuts_off_long_breadcrumbs() {
+        let breadcrumbs = Breadcrumbs::new();
+        breadcrumbs.record(|entry| {
+            _ = ufmt::uwrite!(
+                entry,
+                "{}{}",
+                "x".repeat(BREADCRUMB_CAPACITY - 1).as_str(),
+                "é"
+            );
+        });
+        assert_eq!(collect(&breadcrumbs)[0].len(), BREADCRUMB_CAPACITY - 1);
+    }
+
+    #[test]
+    fn skips_breadcrumb_being_written() {
+        let breadcrumbs = Breadcrumbs::new();
+        record(&breadcrumbs, 1);
+        breadcrumbs.record(|_| {
+            assert_eq!(collect(&breadcrumbs), ["entering state 1"]);
+        });
+    }
+
+    #[test]
+    fn skips_breadcrumb_for_slot_in_use() {
+        let breadcrumbs = Breadcrumbs::new();
+        breadcrumbs.record(|entry| {
+            _ = ufmt::uwrite!(entry, "interrupted");
+            // An interrupt fills the ring, coming around to the slot being written.
+            for n in 1..=BREADCRUMB_COUNT {
+                record(&breadcrumbs, n);
+            }
+            _ = ufmt::uwrite!(entry, " breadcrumb");
+        });
+        // The last one found its slot in use, and the interrupted one is older than the rest.
+        let expected: Vec<_> = (1..BREADCRUMB_COUNT)
+            .map(|n| format!("entering state {n}"))
+            .collect();
+        assert_eq!(collect(&breadcrumbs), expected);
+    }
+
+    #[test]
+    #[cfg(not(feature = "binary"))]
+    fn prints_breadcrumbs() {
+        use crate::tests::Buffer;
+        use crate::DefaultConfig;
+        use std::string::String;
+
+        let breadcrumbs = Breadcrumbs::new();
+        record(&breadcrumbs, 1);
+        record(&breadcrumbs, 2);
+        let mut buffer = Buffer(String::new());
+        print_breadcrumbs::<DefaultConfig, _>(&mut buffer, &breadcrumbs);
+        assert_eq!(
+            buffer.0,
+            "Breadcrumb: entering state 1\r\nBreadcrumb: entering state 2\r\n"
+        );
+    }
+}
@@ -0,0 +1,162 @@
+use crate::RecordBuffer;
+use core::ops::Range;
+use core::panic::PanicInfo;
+
+/// Configures the panic handler defined by [`impl_panic_handler`](crate::impl_panic_handler).
+///
+/// Every item has a default, so an implementation only needs to override what it wants to change:
+/// ```ignore
+/// struct PanicConfig;
+///
+/// impl panic_serial::Config for PanicConfig {
+///     const LINE_ENDING: &'static str = "\n";
+///
+///     fn record() -> Option<&'static panic_serial::RecordBuffer> {
+///         Some(&PANIC_RECORD)
+///     }
+/// }
+///
+/// panic_serial::impl_panic_handler!(MyPort, PanicConfig);
+/// ```
+pub trait Config {
+    /// Ends every line of the report.
+    const LINE_ENDING: &'static str = "\r\n";
+
+    /// Printed before the location (with the `location` feature).
+    const PREFIX: &'static str = "Panic at ";
+
+    /// Printed between the location and the message (with the `full` feature).
+    const SEPARATOR: &'static str = ": ";
+
+    /// Printed instead of the report, if neither `location` nor `message` are enabled.
+    const FALLBACK_MESSAGE: &'static str = "PANIC !";
+
+    /// Printed on a line of its own before the report, if the panic interrupted a line written through the
+    /// [`SharedPort`](crate::SharedPort), so that tools on the other end can tell where the report begins.
+    ///
+    /// If empty, only the line is finished.
+    const SENTINEL: &'static str = "!!! PANIC";
+
+    /// Identifies the firmware, printed on a line of its own before the report (and before HardFaults).
+    ///
+    /// Defaults to an empty string, which leaves out the line unless there is a [`build_id`](Config::build_id).
+    /// [`firmware_id!`](crate::firmware_id) gives the name and version of the crate, and a git hash can be
+    /// added by a build script:
+    /// ```ignore
+    /// const FIRMWARE_ID: &'static str =
+    ///     concat!(panic_serial::firmware_id!(), " ", env!("GIT_HASH"));
+    /// ```
+    const FIRMWARE_ID: &'static str = "";
+
+    /// Build id of the firmware, printed in hex after [`FIRMWARE_ID`](Config::FIRMWARE_ID).
+    ///
+    /// Defaults to none. To use the GNU build id, link with `-C link-arg=--build-id`, keep the note in
+    /// flash with the linker script:
+    /// ```text
+    /// SECTIONS
+    /// {
+    ///   .note.gnu.build-id : { __build_id_start = .; KEEP(*(.note.gnu.build-id)) __build_id_end = .; } > FLASH
+    /// } INSERT AFTER .rodata;
+    /// ```
+    /// and read it with [`gnu_build_id`](crate::gnu_build_id):
+    /// ```ignore
+    /// fn build_id() -> &'static [u8] {
+    ///     extern "C" {
+    ///         static __build_id_start: u8;
+    ///         static __build_id_end: u8;
+    ///     }
+    ///     let (start, end) = unsafe { (&__build_id_start as *const u8, &__build_id_end as *const u8) };
+    ///     panic_serial::gnu_build_id(unsafe { core::slice::from_raw_parts(start, end as usize - start as usize) })
+    /// }
+    /// ```
+    fn build_id() -> &'static [u8] {
+        &[]
+    }
+
+    /// Maximum number of addresses in the backtrace (with the `backtrace` feature).
+    const BACKTRACE_DEPTH: usize = 16;
+
+    /// Addresses of the code, to tell return addresses from other data on the stack (with the `backtrace` feature).
+    ///
+    /// Defaults to an empty range, which leaves out the backtrace. With `cortex-m-rt`, the linker script
+    /// provides the bounds:
+    /// ```ignore
+    /// fn code_range() -> core::ops::Range<usize> {
+    ///     extern "C" {
+    ///         static __stext: u8;
+    ///         static __etext: u8;
+    ///     }
+    ///     unsafe { &__stext as *const u8 as usize..&__etext as *const u8 as usize }
+    /// }
+    /// ```
+    fn code_range() -> Range<usize> {
+        0..0
+    }
+
+    /// Highest address of the stack, up to which the backtrace looks for return addresses (with the `backtrace` feature).
+    ///
+    /// That is `_stack_start` with `cortex-m-rt`.
+    fn stack_start() -> usize {
+        0
+    }
+
+    /// Time of the panic, printed in brackets before the report (and before HardFaults), to match it up with
+    /// logs recorded elsewhere.
+    ///
+    /// Defaults to `None`, which leaves it out. The unit is up to the firmware: milliseconds since boot
+    /// from a timer, or seconds from an RTC. Keep in mind that this is called from the panic handler, so it must
+    /// not panic or take locks itself:
+    /// ```ignore
+    /// fn timestamp() -> Option<u64> {
+    ///     Some(MILLIS.load(Ordering::Relaxed))
+    /// }
+    /// ```
+    fn timestamp() -> Option<u64> {
+        None
+    }
+
+    /// Number of memory regions which can be registered with `dump_on_panic`, see [`Region`](crate::Region).
+    ///
+    /// Defaults to `0`, so that no memory is spent on them.
+    const MAX_REGIONS: usize = 0;
+
+    /// Buffer in which a record of the panic is kept across resets. See [`RecordBuffer`].
+    ///
+    /// Defaults to `None`, which means no record is kept.
+    fn record() -> Option<&'static RecordBuffer> {
+        None
+    }
+
+    /// Called once the panic has been printed. Never returns.
+    ///
+    /// Defaults to [`halt`], which loops forever. Override it to reset the device, trigger the watchdog,
+    /// hit a breakpoint or blink an LED pattern instead:
+    /// ```ignore
+    /// fn after_panic(_info: &PanicInfo) -> ! {
+    ///     cortex_m::peripheral::SCB::sys_reset()
+    /// }
+    /// ```
+    fn after_panic(_info: &PanicInfo) -> ! {
+        halt()
+    }
+
+    /// Called once a HardFault has been printed (with the `cortex-m-rt` feature). Never returns.
+    ///
+    /// Defaults to [`halt`], like [`after_panic`](Config::after_panic).
+    #[cfg(feature = "cortex-m-rt")]
+    fn after_hard_fault(_frame: &cortex_m_rt::ExceptionFrame) -> ! {
+        halt()
+    }
+}
+
+/// Loops forever. This is what the panic handler does after printing, unless configured otherwise.
+pub fn halt() -> ! {
+    loop {
+        core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+/// The configuration used when none is passed to [`impl_panic_handler`](crate::impl_panic_handler).
+pub struct DefaultConfig;
+
+impl Config for DefaultConfig {}
@@ -0,0 +1,39 @@
+/// Incremental CRC-16/CCITT-FALSE (polynomial 0x1021, initial value 0xffff).
+#[derive(Clone, Copy)]
+pub(crate) struct Crc16(u16);
+
+impl Crc16 {
+    pub(crate) const fn new() -> Self {
+        Crc16(0xffff)
+    }
+
+    pub(crate) fn update(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= (*byte as u16) << 8;
+            for _ in 0..8 {
+                self.0 = if self.0 & 0x8000 != 0 {
+                    (self.0 << 1) ^ 0x1021
+                } else {
+                    self.0 << 1
+                };
+            }
+        }
+    }
+
+    pub(crate) fn finish(self) -> u16 {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_value() {
+        let mut crc = Crc16::new();
+        crc.update(b"1234");
+        crc.update(b"56789");
+        assert_eq!(crc.finish(), 0x29b1);
+    }
+}
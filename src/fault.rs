@@ -0,0 +1,104 @@
+//! Printing the state of the CPU on a HardFault, with the `cortex-m-rt` feature.
+
+use crate::frame::{self, Hex};
+use crate::Config;
+use ufmt::uWrite;
+
+/// Names of the registers, in the order they are passed to [`print_fault`].
+const NAMES: [&str; 12] = [
+    "r0", "r1", "r2", "r3", "r12", "lr", "pc", "xpsr", "cfsr", "hfsr", "mmfar", "bfar",
+];
+
+/// Prints the registers of a HardFault: those of the exception frame, followed by the fault status
+/// registers if the target has them.
+pub(crate) fn print_fault<C: Config, W: uWrite + ?Sized>(w: &mut W, registers: &[u32]) {
+    if cfg!(feature = "binary") {
+        frame::write_hard_fault(w, registers, C::LINE_ENDING);
+        return;
+    }
+
+    _ = w.write_str("HardFault");
+    let mut separator = C::SEPARATOR;
+    for (name, value) in NAMES.iter().zip(registers) {
+        _ = ufmt::uwrite!(w, "{}{}=0x{}", separator, name, Hex(*value));
+        separator = " ";
+    }
+    _ = w.write_str(C::LINE_ENDING);
+}
+
+/// Collects the registers to print, returning them along with how many there are.
+#[cfg(feature = "cortex-m-rt")]
+pub(crate) fn registers(frame: &cortex_m_rt::ExceptionFrame) -> ([u32; 12], usize) {
+    let mut registers = [0; 12];
+    registers[..8].copy_from_slice(&[
+        frame.r0(),
+        frame.r1(),
+        frame.r2(),
+        frame.r3(),
+        frame.r12(),
+        frame.lr(),
+        frame.pc(),
+        frame.xpsr(),
+    ]);
+    #[cfg(fault_status)]
+    {
+        // CFSR, HFSR, MMFAR and BFAR of the System Control Block.
+        for (register, address) in registers[8..].iter_mut().zip([
+            0xe000_ed28_usize,
+            0xe000_ed2c,
+            0xe000_ed34,
+            0xe000_ed38,
+        ]) {
+            // SAFETY: these registers exist on this target (see build.rs), and reading them has no side effects.
+            *register = unsafe { core::ptr::read_volatile(address as *const u32) };
+        }
+        return (registers, 12);
+    }
+    #[allow(unreachable_code)]
+    (registers, 8)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::tests::Buffer;
+    use crate::DefaultConfig;
+    use std::string::String;
+
+    #[test]
+    #[cfg(feature = "binary")]
+    fn prints_frame() {
+        let registers = [0, 1, 2, 3, 12, 0x0800_0f01, 0x0800_1234, 0x6100_0000];
+        let mut buffer = Buffer(String::new());
+        print_fault::<DefaultConfig, _>(&mut buffer, &registers);
+        let mut expected = Buffer(String::new());
+        frame::write_hard_fault(&mut expected, &registers, "\r\n");
+        assert_eq!(buffer.0, expected.0);
+    }
+
+    #[test]
+    #[cfg(not(feature = "binary"))]
+    fn prints_registers() {
+        let mut buffer = Buffer(String::new());
+        print_fault::<DefaultConfig, _>(
+            &mut buffer,
+            &[0, 1, 2, 3, 12, 0x0800_0f01, 0x0800_1234, 0x6100_0000],
+        );
+        assert_eq!(
+            buffer.0,
+            "HardFault: r0=0x00000000 r1=0x00000001 r2=0x00000002 r3=0x00000003 r12=0x0000000c \
+             lr=0x08000f01 pc=0x08001234 xpsr=0x61000000\r\n"
+        );
+
+        let mut buffer = Buffer(String::new());
+        print_fault::<DefaultConfig, _>(
+            &mut buffer,
+            &[0, 0, 0, 0, 0, 0, 0, 0, 0x8200, 0x4000_0000, 0, 0x2000_8000],
+        );
+        assert!(buffer
+            .0
+            .ends_with(" cfsr=0x00008200 hfsr=0x40000000 mmfar=0x00000000 bfar=0x20008000\r\n"));
+    }
+}
@@ -0,0 +1,126 @@
+//! Identifying the firmware which panicked.
+
+use crate::frame::{self, HexByte};
+use crate::Config;
+use ufmt::uWrite;
+
+/// Prints [`Config::FIRMWARE_ID`] and [`Config::build_id`] on a line of their own, if there are any.
+pub(crate) fn print_firmware_id<C: Config, W: uWrite + ?Sized>(w: &mut W) {
+    let (id, build_id) = (C::FIRMWARE_ID, C::build_id());
+    if id.is_empty() && build_id.is_empty() {
+        return;
+    }
+    if cfg!(feature = "binary") {
+        frame::write_firmware_id(w, id, build_id, C::LINE_ENDING);
+        return;
+    }
+
+    _ = ufmt::uwrite!(w, "Firmware{}{}", C::SEPARATOR, id);
+    if !build_id.is_empty() {
+        if !id.is_empty() {
+            _ = w.write_str(" ");
+        }
+        for byte in build_id {
+            _ = ufmt::uwrite!(w, "{}", HexByte(*byte));
+        }
+    }
+    _ = w.write_str(C::LINE_ENDING);
+}
+
+/// Returns the build id from a GNU build-id note, as placed by the linker in `.note.gnu.build-id`
+/// (with `-C link-arg=--build-id`).
+///
+/// Returns an empty slice if `note` is not a build-id note.
+pub fn gnu_build_id(note: &[u8]) -> &[u8] {
+    // Header: name size, description size, type, all 4 bytes; then the name ("GNU\0") and the id.
+    const HEADER_LEN: usize = 12;
+    const NAME: &[u8] = b"GNU\0";
+    const NT_GNU_BUILD_ID: u32 = 3;
+    if note.len() < HEADER_LEN {
+        return &[];
+    }
+    let word = |i: usize| u32::from_ne_bytes([note[i], note[i + 1], note[i + 2], note[i + 3]]);
+    let (name_len, id_len, kind) = (word(0) as usize, word(4) as usize, word(8));
+    if name_len != NAME.len() || kind != NT_GNU_BUILD_ID {
+        return &[];
+    }
+    // The name is padded to 4 bytes, which "GNU\0" already is.
+    let start = HEADER_LEN + NAME.len();
+    match start.checked_add(id_len) {
+        Some(end) if note.len() >= end && note[HEADER_LEN..start] == *NAME => &note[start..end],
+        _ => &[],
+    }
+}
+
+/// Expands to the name and version of the crate it is used in, e.g. `"blinky 0.3.1"`, for use as
+/// [`Config::FIRMWARE_ID`].
+#[macro_export]
+macro_rules! firmware_id {
+    () => {
+        ::core::concat!(
+            ::core::env!("CARGO_PKG_NAME"),
+            " ",
+            ::core::env!("CARGO_PKG_VERSION")
+        )
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+
+    #[test]
+    fn reads_gnu_build_id() {
+        let mut note = std::vec::Vec::new();
+        for word in [4u32, 3, 3] {
+            note.extend_from_slice(&word.to_ne_bytes());
+        }
+        note.extend_from_slice(b"GNU\0");
+        note.extend_from_slice(&[0xab, 0xcd, 0xef]);
+        assert_eq!(gnu_build_id(&note), [0xab, 0xcd, 0xef]);
+        assert_eq!(gnu_build_id(&note[..17]), []);
+        assert_eq!(gnu_build_id(&[]), []);
+
+        let mut foreign = note.clone();
+        foreign[12..16].copy_from_slice(b"Go\0\0");
+        assert_eq!(gnu_build_id(&foreign), []);
+
+        let mut corrupt = note.clone();
+        corrupt[4..8].copy_from_slice(&u32::MAX.to_ne_bytes());
+        assert_eq!(gnu_build_id(&corrupt), []);
+    }
+
+    #[test]
+    #[cfg(not(feature = "binary"))]
+    fn prints_firmware_id() {
+        use crate::tests::Buffer;
+        use crate::DefaultConfig;
+        use std::string::String;
+
+        struct Identified;
+
+        impl Config for Identified {
+            const FIRMWARE_ID: &'static str = crate::firmware_id!();
+
+            fn build_id() -> &'static [u8] {
+                &[0x3f, 0x2a, 0x01]
+            }
+        }
+
+        let mut buffer = Buffer(String::new());
+        print_firmware_id::<Identified, _>(&mut buffer);
+        assert_eq!(
+            buffer.0,
+            std::format!(
+                "Firmware: panic-serial {} 3f2a01\r\n",
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+
+        let mut buffer = Buffer(String::new());
+        print_firmware_id::<DefaultConfig, _>(&mut buffer);
+        assert_eq!(buffer.0, "");
+    }
+}
@@ -0,0 +1,54 @@
+use crate::Config;
+use core::fmt::Display;
+use core::panic::Location;
+use ufmt::uWrite;
+
+/// Writes the report of a panic.
+///
+/// The panic handler defined by [`impl_panic_handler`](crate::impl_panic_handler) uses [`DefaultFormatter`],
+/// unless another formatter is given as third argument:
+/// ```ignore
+/// struct JsonFormatter;
+///
+/// impl panic_serial::PanicFormatter for JsonFormatter {
+///     fn format<C: panic_serial::Config, W: ufmt::uWrite, M: core::fmt::Display>(
+///         w: &mut W,
+///         location: Option<&core::panic::Location<'_>>,
+///         message: M,
+///     ) {
+///         // ...
+///     }
+/// }
+///
+/// panic_serial::impl_panic_handler!(MyPort, panic_serial::DefaultConfig, JsonFormatter);
+/// ```
+/// Breadcrumbs, backtraces and memory regions are still printed after the report. The report isn't prefixed
+/// with [`Config::timestamp`]; call it from the formatter to include it.
+///
+/// With the `defmt` feature, the report is logged with `defmt` instead, and the formatter is not used.
+pub trait PanicFormatter {
+    /// Writes the report of a panic at `location` (if known) with the given `message` to `w`.
+    ///
+    /// Unlike the default formatter, this gets the location and message regardless of the `location` and
+    /// `message` features. Keep in mind that formatting the message through `core::fmt` takes up a lot of space.
+    fn format<C: Config, W: uWrite, M: Display>(
+        w: &mut W,
+        location: Option<&Location<'_>>,
+        message: M,
+    );
+}
+
+/// Writes the report as described in the crate documentation, according to the enabled features and the
+/// [`Config`].
+pub struct DefaultFormatter;
+
+impl PanicFormatter for DefaultFormatter {
+    fn format<C: Config, W: uWrite, M: Display>(
+        w: &mut W,
+        location: Option<&Location<'_>>,
+        message: M,
+    ) {
+        crate::timestamp::print_timestamp::<C, W>(w);
+        crate::print_report::<C, W, M>(w, location, message);
+    }
+}
@@ -0,0 +1,442 @@
+//! Compact binary panic frames, for links where every byte counts.
+//!
+//! With the `binary` feature, the panic handler writes a frame instead of the text report.
+//! Since ports only take `&str`, the frame is made of text as well:
+//! ```text
+//! START  base64(kind, line, column, file hash, message, crc16)  line ending
+//! ```
+//! - `kind`: one byte, [`KIND_PANIC`]
+//! - `line`, `column`: LEB128 encoded, `0` if there is no location
+//! - `file hash`: [`file_hash`] of the file name, 4 bytes little endian, `0` if there is no location
+//! - `message`: the UTF-8 message, up to the checksum
+//! - `crc16`: [`crc16`] of everything before, 2 bytes little endian
+//!
+//! HardFaults (see the `cortex-m-rt` feature) are sent as frames of kind [`KIND_HARD_FAULT`], which contain the
+//! registers r0-r3, r12, lr, pc and xpsr, followed by CFSR, HFSR, MMFAR and BFAR where the target has them,
+//! each 4 bytes little endian.
+//!
+//! Backtraces (see the `backtrace` feature) follow the panic as frames of kind [`KIND_BACKTRACE`], which contain
+//! the return addresses, each 4 bytes little endian.
+//!
+//! Memory regions (see `Config::MAX_REGIONS`) follow the panic as frames of kind [`KIND_REGION`], which contain
+//! the address (4 bytes little endian), the length of the name (LEB128 encoded), the name and the bytes of the region.
+//!
+//! Breadcrumbs (see the `breadcrumbs` feature) follow the panic as frames of kind [`KIND_BREADCRUMB`], which
+//! contain the text of the breadcrumb.
+//!
+//! The firmware id (see `Config::FIRMWARE_ID` and `Config::build_id`) precedes the panic or HardFault as a frame
+//! of kind [`KIND_FIRMWARE`], which contains the length of the id (LEB128 encoded), the id and the build id.
+//!
+//! The timestamp (see `Config::timestamp`) precedes the panic or HardFault as a frame of kind [`KIND_TIMESTAMP`],
+//! which contains the timestamp, 8 bytes little endian.
+//!
+//! The base64 alphabet is the standard one, without padding. The line ending is [`Config::LINE_ENDING`](crate::Config::LINE_ENDING). The location is only included with the `location`
+//! feature, the message only with `message`, just like in the text report.
+//!
+//! The `panic-serial-decode` tool in this repository turns frames back into readable reports.
+
+use crate::crc::Crc16;
+use core::fmt::{Display, Write};
+use core::panic::Location;
+use ufmt::uWrite;
+
+/// Starts a frame (ASCII "start of heading").
+pub const START: char = '\x01';
+
+/// Kind of frame describing a panic.
+pub const KIND_PANIC: u8 = 1;
+
+/// Kind of frame describing a HardFault.
+pub const KIND_HARD_FAULT: u8 = 2;
+
+/// Kind of frame listing the return addresses found on the stack.
+pub const KIND_BACKTRACE: u8 = 3;
+
+/// Kind of frame containing a memory region.
+pub const KIND_REGION: u8 = 4;
+
+/// Kind of frame containing a breadcrumb.
+pub const KIND_BREADCRUMB: u8 = 5;
+
+/// Kind of frame identifying the firmware.
+pub const KIND_FIRMWARE: u8 = 6;
+
+/// Kind of frame telling when the panic happened.
+pub const KIND_TIMESTAMP: u8 = 7;
+
+/// Hashes a file name (32 bit FNV-1a), to identify it without sending the whole path.
+pub const fn file_hash(file: &str) -> u32 {
+    let bytes = file.as_bytes();
+    let mut hash = 0x811c_9dc5_u32;
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u32;
+        hash = hash.wrapping_mul(0x0100_0193);
+        i += 1;
+    }
+    hash
+}
+
+/// Prints a file hash (or an address, or register) as 8 hex digits, as done in text reports with the `file-hash` feature.
+pub(crate) struct Hex(pub(crate) u32);
+
+impl Hex {
+    /// The digits as printed.
+    pub(crate) fn digits(&self) -> [u8; 8] {
+        let mut digits = [0; 8];
+        for (i, digit) in digits.iter_mut().enumerate() {
+            *digit = HEX_DIGITS[(self.0 >> (28 - 4 * i) & 0xf) as usize];
+        }
+        digits
+    }
+}
+
+impl ufmt::uDisplay for Hex {
+    fn fmt<W: uWrite + ?Sized>(&self, f: &mut ufmt::Formatter<'_, W>) -> Result<(), W::Error> {
+        f.write_str(core::str::from_utf8(&self.digits()).unwrap_or_default())
+    }
+}
+
+/// Prints a byte as 2 hex digits.
+pub(crate) struct HexByte(pub(crate) u8);
+
+impl ufmt::uDisplay for HexByte {
+    fn fmt<W: uWrite + ?Sized>(&self, f: &mut ufmt::Formatter<'_, W>) -> Result<(), W::Error> {
+        let digits = [
+            HEX_DIGITS[(self.0 >> 4) as usize],
+            HEX_DIGITS[(self.0 & 0xf) as usize],
+        ];
+        f.write_str(core::str::from_utf8(&digits).unwrap_or_default())
+    }
+}
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+/// Checksum used by frames (CRC-16/CCITT-FALSE).
+pub fn crc16(bytes: &[u8]) -> u16 {
+    let mut crc = Crc16::new();
+    crc.update(bytes);
+    crc.finish()
+}
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Writes the given panic as a frame, followed by `line_ending`.
+pub fn write_panic<W: uWrite + ?Sized, M: Display>(
+    w: &mut W,
+    location: Option<&Location>,
+    message: M,
+    line_ending: &str,
+) {
+    let mut line = [0; 5];
+    let mut column = [0; 5];
+    let mut hash = 0;
+    let (mut line_len, mut column_len) = (leb128(0, &mut line), leb128(0, &mut column));
+    if cfg!(feature = "location") {
+        if let Some(location) = location {
+            line_len = leb128(location.line(), &mut line);
+            column_len = leb128(location.column(), &mut column);
+            hash = file_hash(location.file());
+        }
+    }
+
+    let mut encoder = Encoder::start(w, KIND_PANIC);
+    encoder.push(&line[..line_len]);
+    encoder.push(&column[..column_len]);
+    encoder.push(&hash.to_le_bytes());
+    if cfg!(feature = "message") {
+        _ = write!(encoder, "{}", message);
+    }
+    encoder.end(line_ending);
+}
+
+/// Writes the registers of a HardFault as a frame, followed by `line_ending`.
+pub fn write_hard_fault<W: uWrite + ?Sized>(w: &mut W, registers: &[u32], line_ending: &str) {
+    let mut encoder = Encoder::start(w, KIND_HARD_FAULT);
+    for register in registers {
+        encoder.push(&register.to_le_bytes());
+    }
+    encoder.end(line_ending);
+}
+
+/// Writes return addresses as a frame, followed by `line_ending`.
+pub fn write_backtrace<W: uWrite + ?Sized>(
+    w: &mut W,
+    addresses: impl Iterator<Item = u32>,
+    line_ending: &str,
+) {
+    let mut encoder = Encoder::start(w, KIND_BACKTRACE);
+    for address in addresses {
+        encoder.push(&address.to_le_bytes());
+    }
+    encoder.end(line_ending);
+}
+
+/// Writes a memory region as a frame, followed by `line_ending`.
+pub fn write_region<W: uWrite + ?Sized>(
+    w: &mut W,
+    name: &str,
+    address: u32,
+    bytes: impl Iterator<Item = u8>,
+    line_ending: &str,
+) {
+    let mut name_len = [0; 5];
+    let name_len_len = leb128(name.len() as u32, &mut name_len);
+    let mut encoder = Encoder::start(w, KIND_REGION);
+    encoder.push(&address.to_le_bytes());
+    encoder.push(&name_len[..name_len_len]);
+    encoder.push(name.as_bytes());
+    for byte in bytes {
+        encoder.push(&[byte]);
+    }
+    encoder.end(line_ending);
+}
+
+/// Writes a breadcrumb as a frame, followed by `line_ending`.
+pub fn write_breadcrumb<W: uWrite + ?Sized>(w: &mut W, breadcrumb: &str, line_ending: &str) {
+    let mut encoder = Encoder::start(w, KIND_BREADCRUMB);
+    encoder.push(breadcrumb.as_bytes());
+    encoder.end(line_ending);
+}
+
+/// Writes the firmware id and build id as a frame, followed by `line_ending`.
+pub fn write_firmware_id<W: uWrite + ?Sized>(
+    w: &mut W,
+    id: &str,
+    build_id: &[u8],
+    line_ending: &str,
+) {
+    let mut id_len = [0; 5];
+    let id_len_len = leb128(id.len() as u32, &mut id_len);
+    let mut encoder = Encoder::start(w, KIND_FIRMWARE);
+    encoder.push(&id_len[..id_len_len]);
+    encoder.push(id.as_bytes());
+    encoder.push(build_id);
+    encoder.end(line_ending);
+}
+
+/// Writes a timestamp as a frame, followed by `line_ending`.
+pub fn write_timestamp<W: uWrite + ?Sized>(w: &mut W, timestamp: u64, line_ending: &str) {
+    let mut encoder = Encoder::start(w, KIND_TIMESTAMP);
+    encoder.push(&timestamp.to_le_bytes());
+    encoder.end(line_ending);
+}
+
+/// Encodes `value` into `buf`, returning the number of bytes used.
+fn leb128(mut value: u32, buf: &mut [u8; 5]) -> usize {
+    let mut len = 0;
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            buf[len] = byte;
+            return len + 1;
+        }
+        buf[len] = byte | 0x80;
+        len += 1;
+    }
+}
+
+/// Base64 encodes bytes on the fly, keeping track of their checksum.
+struct Encoder<'a, W: uWrite + ?Sized> {
+    w: &'a mut W,
+    crc: Crc16,
+    pending: [u8; 3],
+    pending_len: usize,
+}
+
+impl<'a, W: uWrite + ?Sized> Encoder<'a, W> {
+    /// Writes the start of a frame of the given kind.
+    fn start(w: &'a mut W, kind: u8) -> Self {
+        let mut encoder = Self::new(w);
+        _ = encoder.w.write_char(START);
+        encoder.push(&[kind]);
+        encoder
+    }
+
+    fn new(w: &'a mut W) -> Self {
+        Encoder {
+            w,
+            crc: Crc16::new(),
+            pending: [0; 3],
+            pending_len: 0,
+        }
+    }
+
+    /// Writes the checksum and the end of the frame.
+    fn end(mut self, line_ending: &str) {
+        let crc = self.crc.finish();
+        self.push(&crc.to_le_bytes());
+        self.finish();
+        _ = self.w.write_str(line_ending);
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        self.crc.update(bytes);
+        for byte in bytes {
+            self.pending[self.pending_len] = *byte;
+            self.pending_len += 1;
+            if self.pending_len == 3 {
+                self.flush_pending();
+            }
+        }
+    }
+
+    fn flush_pending(&mut self) {
+        let [a, b, c] = self.pending;
+        let chars = [
+            ALPHABET[(a >> 2) as usize],
+            ALPHABET[((a & 0x03) << 4 | b >> 4) as usize],
+            ALPHABET[((b & 0x0f) << 2 | c >> 6) as usize],
+            ALPHABET[(c & 0x3f) as usize],
+        ];
+        // Partial groups leave out the characters which only carry padding bits.
+        let len = if self.pending_len == 3 {
+            4
+        } else {
+            self.pending_len + 1
+        };
+        if let Ok(chars) = core::str::from_utf8(&chars[..len]) {
+            _ = self.w.write_str(chars);
+        }
+        self.pending = [0; 3];
+        self.pending_len = 0;
+    }
+
+    fn finish(&mut self) {
+        if self.pending_len > 0 {
+            self.flush_pending();
+        }
+    }
+}
+
+impl<W: uWrite + ?Sized> Write for Encoder<'_, W> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.push(s.as_bytes());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::tests::Buffer;
+    use std::string::String;
+
+    #[test]
+    fn file_hash_is_fnv1a() {
+        assert_eq!(file_hash(""), 0x811c9dc5);
+        assert_eq!(file_hash("a"), 0xe40c292c);
+        assert_eq!(file_hash("foobar"), 0xbf9cf968);
+    }
+
+    #[test]
+    fn hex_is_zero_padded() {
+        let mut buffer = Buffer(String::new());
+        ufmt::uwrite!(
+            buffer,
+            "{} {} {}",
+            Hex(0x1660407c),
+            Hex(0xab),
+            HexByte(0x0f)
+        )
+        .unwrap();
+        assert_eq!(buffer.0, "1660407c 000000ab 0f");
+    }
+
+    #[test]
+    fn leb128_encoding() {
+        let mut buf = [0; 5];
+        assert_eq!(leb128(91, &mut buf), 1);
+        assert_eq!(buf[0], 91);
+        assert_eq!(leb128(300, &mut buf), 2);
+        assert_eq!(buf[..2], [0xac, 0x02]);
+    }
+
+    fn base64(bytes: &[u8]) -> String {
+        let mut buffer = Buffer(String::new());
+        let mut encoder = Encoder::new(&mut buffer);
+        encoder.push(bytes);
+        encoder.finish();
+        buffer.0
+    }
+
+    #[test]
+    fn base64_without_padding() {
+        assert_eq!(base64(b"Man"), "TWFu");
+        assert_eq!(base64(b"Ma"), "TWE");
+        assert_eq!(base64(b"M"), "TQ");
+        assert_eq!(base64(&[0xff, 0xfe, 0xfd, 0xfc]), "//79/A");
+    }
+
+    #[test]
+    fn frame_without_location() {
+        let mut buffer = Buffer(String::new());
+        write_panic(&mut buffer, None, "hi", "\r\n");
+
+        let mut body = std::vec![KIND_PANIC, 0, 0, 0, 0, 0, 0];
+        if cfg!(feature = "message") {
+            body.extend_from_slice(b"hi");
+        }
+        body.extend_from_slice(&crc16(&body).to_le_bytes());
+
+        assert_eq!(buffer.0, std::format!("\x01{}\r\n", base64(&body)));
+        if !cfg!(feature = "message") {
+            assert_eq!(buffer.0, "\x01AQAAAAAAAK9J\r\n");
+        }
+    }
+
+    #[test]
+    fn backtrace_frame() {
+        let mut buffer = Buffer(String::new());
+        write_backtrace(&mut buffer, [0x0800_1235].into_iter(), "\n");
+
+        let mut body = std::vec![KIND_BACKTRACE, 0x35, 0x12, 0x00, 0x08];
+        body.extend_from_slice(&crc16(&body).to_le_bytes());
+        assert_eq!(buffer.0, std::format!("\x01{}\n", base64(&body)));
+    }
+
+    #[test]
+    fn region_frame() {
+        let mut buffer = Buffer(String::new());
+        write_region(&mut buffer, "ab", 0x2000_0100, [7, 8].into_iter(), "\n");
+
+        let mut body = std::vec![KIND_REGION, 0x00, 0x01, 0x00, 0x20, 2, b'a', b'b', 7, 8];
+        body.extend_from_slice(&crc16(&body).to_le_bytes());
+        assert_eq!(buffer.0, std::format!("\x01{}\n", base64(&body)));
+    }
+
+    #[test]
+    fn firmware_id_frame() {
+        let mut buffer = Buffer(String::new());
+        write_firmware_id(&mut buffer, "fw 1.0", &[0x3f, 0x2a], "\n");
+
+        let mut body = std::vec![KIND_FIRMWARE, 6];
+        body.extend_from_slice(b"fw 1.0");
+        body.extend_from_slice(&[0x3f, 0x2a]);
+        body.extend_from_slice(&crc16(&body).to_le_bytes());
+        assert_eq!(buffer.0, std::format!("\x01{}\n", base64(&body)));
+    }
+
+    #[test]
+    fn timestamp_frame() {
+        let mut buffer = Buffer(String::new());
+        write_timestamp(&mut buffer, 0x0102_0304_0506, "\n");
+
+        let mut body = std::vec![KIND_TIMESTAMP, 6, 5, 4, 3, 2, 1, 0, 0];
+        body.extend_from_slice(&crc16(&body).to_le_bytes());
+        assert_eq!(buffer.0, std::format!("\x01{}\n", base64(&body)));
+    }
+
+    #[test]
+    fn hard_fault_frame() {
+        let mut buffer = Buffer(String::new());
+        write_hard_fault(&mut buffer, &[1, 0x0800_1234], "\n");
+
+        let mut body = std::vec![KIND_HARD_FAULT, 1, 0, 0, 0, 0x34, 0x12, 0x00, 0x08];
+        body.extend_from_slice(&crc16(&body).to_le_bytes());
+        assert_eq!(buffer.0, std::format!("\x01{}\n", base64(&body)));
+    }
+}
@@ -1,4 +1,4 @@
-//! Prints panic information via a serial port, then goes into an infinite loop.
+//! Prints panic information via a serial port, then goes into an infinite loop (or whatever else is configured).
 //!
 //! Status: experimental; biased towards Arduino
 //!
@@ -16,25 +16,139 @@
 //! The level of detail is chosen by selecting **feature flags**:
 //! - `location`: prints location information.
 //!   Example:
-//!   ```
+//!   ```text
 //!   Panic at src/main.rs:91:9
 //!   ```
 //! - `message`: prints the actual full panic message. This uses `core::fmt` under the hood, so expect an increase in firmware size.
 //!   Example:
-//!   ```
+//!   ```text
 //!   attempt to subtract with overflow
 //!   ```
 //! - `full` == `location` & `message`: Combined location and message.
-//!    Example:
-//!    ```
-//!    Panic at src/main.rs:91:9: attempt to subtract with overflow
-//!    ```
+//!   Example:
+//!   ```text
+//!   Panic at src/main.rs:91:9: attempt to subtract with overflow
+//!   ```
 //! - (no features): if no features are chosen, a static message is printed.
-//!    Example:
-//!    ```
-//!    PANIC !
-//!    ```
-//!    This option is easiest on firmware size.
+//!   Example:
+//!   ```text
+//!   PANIC !
+//!   ```
+//!   This option is easiest on firmware size.
+//! - `binary`: instead of text, a compact frame is printed (see [`frame`] for the format), which also includes
+//!   the location and message if the features above are enabled.
+//!   The `panic-serial-decode` tool (in the `decoder` directory of the repository) turns frames back into text:
+//!   ```sh
+//!   cargo run -p panic-serial-decoder < serial-output.txt
+//!   ```
+//! - `file-hash`: with `location`, prints a 32 bit hash of the file name instead of the name itself, which keeps
+//!   the reports short.
+//!   Example:
+//!   ```text
+//!   Panic at #1660407c:91:9
+//!   ```
+//!   This only shortens the reports, it does not make the firmware any smaller: the compiler stores the file name with
+//!   every panic location either way, and the hash is computed from it when printing.
+//!   Since the names are in the firmware, `panic-serial-decode` gets them back: pass it the firmware ELF (or the source tree)
+//!   with `--paths`:
+//!   ```sh
+//!   cargo run -p panic-serial-decoder -- --paths target/avr-atmega328p/release/firmware.elf < serial-output.txt
+//!   ```
+//!   Keeping file names out of flash takes a nightly compiler and `-Zlocation-detail=line,column`, which leaves no file
+//!   name to hash, or to get back.
+//! - `backtrace`: after the report, prints the return addresses found on the stack, for when the location alone
+//!   does not tell how the panic was reached. It needs to know where the code and the stack are, see
+//!   [`Config::code_range`] and [`Config::stack_start`].
+//!   Example:
+//!   ```text
+//!   Backtrace: 0x08000ddd 0x08001e41
+//!   ```
+//!   The stack is scanned word by word, which suits 32 bit targets like Cortex-M. AVR stores return addresses
+//!   differently, so they are not found there.
+//!   `panic-serial-decode` symbolizes them with the debug information of the firmware:
+//!   ```sh
+//!   cargo run -p panic-serial-decoder -- --elf target/thumbv7em-none-eabihf/debug/firmware < serial-output.txt
+//!   ```
+//! - `breadcrumbs`: prints the last few notes left with `breadcrumb!` after the message, to tell what the firmware was
+//!   doing before it panicked:
+//!   ```ignore
+//!   panic_serial::breadcrumb!("entering state {}", state);
+//!   ```
+//!   Example:
+//!   ```text
+//!   Panic at src/main.rs:91:9: attempt to subtract with overflow
+//!   Breadcrumb: received command 7
+//!   Breadcrumb: entering state 3
+//!   ```
+//!   The last [`BREADCRUMB_COUNT`] (8) breadcrumbs are kept, each cut off after [`BREADCRUMB_CAPACITY`] (32) bytes.
+//!   Without the feature, `breadcrumb!` does nothing.
+//! - `defmt`: instead of printing to the ports, logs the report with `defmt::error!` over the global logger the
+//!   firmware already uses (e.g. `defmt-rtt`), so it is decoded by `defmt-print` or `probe-rs` along with the rest of the log.
+//!   The location and message (as selected by the features above), breadcrumbs, backtrace and memory regions each
+//!   become an error frame:
+//!   ```text
+//!   ERROR Panic at src/main.rs:91:9: attempt to subtract with overflow
+//!   ```
+//!   The firmware id and `Config::timestamp` are left out, `defmt` has its own ways to tell these, and a custom
+//!   `PanicFormatter` is not used (the report is always logged in the layout above). Since the ports are not written to,
+//!   no port needs to be shared. `impl_panic_handler` still needs a port type though, which has `uWrite` and `flush` like
+//!   any port; the firmware's UART type will do.
+//!   A panic within the logger itself (say, in the middle of a `defmt::info!`) cannot be logged.
+//!
+//! The line ending (`\r\n`), the prefix (`Panic at `), the separator (`: `) and the fallback message (`PANIC !`)
+//! can be changed through [`Config`]:
+//! ```ignore
+//! struct PanicConfig;
+//!
+//! impl panic_serial::Config for PanicConfig {
+//!     const LINE_ENDING: &'static str = "\n";
+//!     const PREFIX: &'static str = "panicked at ";
+//! }
+//!
+//! panic_serial::impl_panic_handler!(MyPort, PanicConfig);
+//! ```
+//!
+//! ## Custom formats
+//!
+//! To print panics in a format of your own (JSON lines, a protocol header, a version string, ...), implement
+//! [`PanicFormatter`] and pass it as third argument:
+//! ```ignore
+//! struct JsonFormatter;
+//!
+//! impl panic_serial::PanicFormatter for JsonFormatter {
+//!     fn format<C: panic_serial::Config, W: ufmt::uWrite, M: core::fmt::Display>(
+//!         w: &mut W,
+//!         location: Option<&core::panic::Location<'_>>,
+//!         message: M,
+//!     ) {
+//!         // Write `location` and `message` to `w`.
+//!     }
+//! }
+//!
+//! panic_serial::impl_panic_handler!(MyPort, panic_serial::DefaultConfig, JsonFormatter);
+//! ```
+//! The formatter gets the location and message no matter which features are enabled. The port sharing, the record,
+//! breadcrumbs, backtraces and memory regions work just the same. The report isn't prefixed with the timestamp,
+//! call `C::timestamp()` from the formatter to include it.
+//!
+//! ## Rust version
+//!
+//! Works on stable Rust. With Rust 1.81 and later the message is taken from the stabilized `PanicInfo::message`.
+//! Older nightlies are detected by the build script and use `feature(panic_info_message)` instead;
+//! older stable compilers print the whole `PanicInfo` (which includes the location) as the message.
+//!
+//! ## Targets without compare-and-swap
+//!
+//! The shared port, memory regions and breadcrumbs are tracked with atomic compare-and-swap, which some targets lack:
+//! ARMv6-M (`thumbv6m-none-eabi`, like the RP2040 or STM32F0) and RISC-V without the A extension (`riscv32imc-unknown-none-elf`,
+//! like the ESP32-C3). On those, one of these features is needed (AVR works without):
+//! - `critical-section`: emulates it with critical sections, as provided by the firmware's `critical-section` implementation
+//!   (e.g. the HAL's, or `cortex-m`'s `critical-section-single-core` feature). This also makes the port be used within critical
+//!   sections, see "Using the port from interrupts".
+//! - `unsafe-assume-single-core`: emulates it by disabling interrupts, assuming that only one core runs (and in privileged
+//!   mode on ARM). Not for multi-core chips like the RP2040.
+//!
+//! The two cannot be combined.
 //!
 //! ## Usage
 //!
@@ -47,7 +161,7 @@
 //!    cargo add panic-serial --features full
 //!    ```
 //! 3. Within your `main.rs` (or elsewhere at top level) invoke the `impl_panic_handler` macro:
-//!    ```
+//!    ```ignore
 //!    panic_serial::impl_panic_handler!(
 //!      // This is the type of the UART port to use for printing the message:
 //!      arduino_hal::usart::Usart<
@@ -61,13 +175,13 @@
 //!   - define the actual panic handler
 //!   - define a function called `share_serial_port_with_panic`, which we'll use in the next step
 //! 4. Call `share_serial_port_with_panic` within `main`:
-//!    ```
+//!    ```ignore
 //!    #[arduino_hal::entry]
 //!    fn main() -> ! {
 //!      // ...
 //!      let serial = arduino_hal::default_serial!(dp, pins, 57600);
-//!      // this gives ownership of the serial port to panic-serial. We receive a mutable reference to it though, so we can keep using it.
-//!      let serial = share_serial_port_with_panic(serial);
+//!      // this gives ownership of the serial port to panic-serial. We receive a handle to it though, so we can keep using it.
+//!      let mut serial = share_serial_port_with_panic(serial).unwrap();
 //!      // continue using serial:
 //!      ufmt::uwriteln!(serial, "Hello there!\r").unwrap();
 //!
@@ -75,31 +189,318 @@
 //!    }
 //!    ```
 //!
+//! ## After the panic
+//!
+//! By default the handler loops forever once the panic is printed. To do something else (reset the device,
+//! trigger the watchdog, hit a breakpoint, blink an LED, ...), override [`Config::after_panic`]:
+//! ```ignore
+//! struct PanicConfig;
+//!
+//! impl panic_serial::Config for PanicConfig {
+//!     fn after_panic(_info: &core::panic::PanicInfo) -> ! {
+//!         cortex_m::peripheral::SCB::sys_reset()
+//!     }
+//! }
+//!
+//! panic_serial::impl_panic_handler!(MyPort, PanicConfig);
+//! ```
+//!
+//! ## Keeping the last panic across resets
+//!
+//! Boards are often reset (by a watchdog, or by someone power-cycling them) before anyone reads the serial output.
+//! To not lose the panic in that case, a compact record of it (location, message cut off after [`MESSAGE_CAPACITY`] bytes,
+//! and a checksum) can be kept in RAM which is not initialized on startup:
+//! ```ignore
+//! #[link_section = ".uninit.PANIC_RECORD"] // `.noinit` on AVR
+//! static PANIC_RECORD: panic_serial::RecordBuffer = panic_serial::RecordBuffer::uninit();
+//!
+//! struct PanicConfig;
+//!
+//! impl panic_serial::Config for PanicConfig {
+//!     fn record() -> Option<&'static panic_serial::RecordBuffer> {
+//!         Some(&PANIC_RECORD)
+//!     }
+//! }
+//!
+//! panic_serial::impl_panic_handler!(MyPort, PanicConfig);
+//! ```
+//! After the next reset, `take_last_panic` returns the record (once), so it can be reported in the layout of the config:
+//! ```ignore
+//! let mut serial = share_serial_port_with_panic(serial).unwrap();
+//! if let Some(record) = take_last_panic() {
+//!     ufmt::uwriteln!(serial, "Previous {}\r", record.display::<PanicConfig>()).unwrap();
+//! }
+//! ```
+//! With the `file-hash` feature, the record keeps the hash of the file name, as printed in the report.
+//!
+//! ## Dumping memory
+//!
+//! A panic message rarely tells the whole story. Register pieces of memory worth looking at (a state struct, a buffer of
+//! the last commands, ...) with `dump_on_panic`, and they are dumped after the report:
+//! ```ignore
+//! struct PanicConfig;
+//!
+//! impl panic_serial::Config for PanicConfig {
+//!     // Number of regions which can be registered, 0 by default.
+//!     const MAX_REGIONS: usize = 2;
+//! }
+//!
+//! panic_serial::impl_panic_handler!(MyPort, PanicConfig);
+//!
+//! #[repr(C)]
+//! #[derive(Clone, Copy)]
+//! struct State {
+//!     mode: u32,
+//!     errors: u32,
+//!     last_command: [u8; 12],
+//! }
+//!
+//! static STATE: Mutex<Cell<State>> = Mutex::new(Cell::new(State::new()));
+//!
+//! // SAFETY: `State` is plain data without padding, and it is never borrowed mutably.
+//! dump_on_panic(unsafe { panic_serial::Region::new("state", &STATE) }).unwrap();
+//! ```
+//! `Region::new` is unsafe because every byte is read: see its docs for what can be dumped. Each region is printed
+//! with its name, address and length, followed by its bytes:
+//! ```text
+//! Region state at 0x20000100 (20 bytes)
+//! 20000100 00 01 02 03 04 05 06 07 08 09 0a 0b 0c 0d 0e 0f
+//! 20000110 a0 b1 c2 d3
+//! ```
+//! With the `binary` feature, a frame is sent for every region instead.
+//!
+//! ## Identifying the firmware
+//!
+//! When reports come from many devices, they need to say which build produced them. Set `Config::FIRMWARE_ID`
+//! (and/or `Config::build_id`), and a line identifying the firmware is printed before every report and HardFault:
+//! ```ignore
+//! struct PanicConfig;
+//!
+//! impl panic_serial::Config for PanicConfig {
+//!     // Name and version of the crate, e.g. "blinky 0.3.1".
+//!     const FIRMWARE_ID: &'static str = panic_serial::firmware_id!();
+//! }
+//!
+//! panic_serial::impl_panic_handler!(MyPort, PanicConfig);
+//! ```
+//! ```text
+//! Firmware: blinky 0.3.1 3f2a01c4
+//! Panic at src/main.rs:91:9: attempt to subtract with overflow
+//! ```
+//! The id can be any string, like a git hash passed in by a build script. `Config::build_id` adds the GNU build id
+//! of the ELF (hex encoded), see its documentation for how to keep it in flash and read it with `gnu_build_id`.
+//! With the `binary` feature, a frame is sent instead.
+//!
+//! ## Timestamps
+//!
+//! To match panics up with logs recorded on the other end of the serial line, implement `Config::timestamp`, and the
+//! report is prefixed with whatever it returns (milliseconds since boot, seconds from an RTC, ...):
+//! ```ignore
+//! struct PanicConfig;
+//!
+//! impl panic_serial::Config for PanicConfig {
+//!     fn timestamp() -> Option<u64> {
+//!         Some(MILLIS.load(Ordering::Relaxed))
+//!     }
+//! }
+//! ```
+//! ```text
+//! [86400123] Panic at src/main.rs:91:9: attempt to subtract with overflow
+//! ```
+//! HardFaults are prefixed the same way. With the `binary` feature, a frame is sent before the panic instead.
+//! A custom formatter writes the timestamp itself, if it wants one.
+//!
+//! ## HardFaults
+//!
+//! On Cortex-M, crashes often end in a HardFault rather than a panic. With the `cortex-m-rt` feature, `impl_panic_handler`
+//! also defines the `HardFault` exception handler, which prints the exception frame and, on ARMv7-M and ARMv8-M Mainline,
+//! the fault status registers to the shared port(s):
+//! ```text
+//! HardFault: r0=0x00000000 r1=0x00000001 r2=0x00000002 r3=0x00000003 r12=0x0000000c lr=0x08000f01 pc=0x08001234 xpsr=0x61000000 cfsr=0x00008200 hfsr=0x40000000 mmfar=0x00000000 bfar=0x20008000
+//! ```
+//! The line ending and separator are taken from `Config`, and with the `binary` feature a frame is sent instead.
+//! Afterwards `Config::after_hard_fault` is called, which halts by default.
+//! The firmware needs to depend on `cortex-m-rt` itself (which it does anyway), and must not define its own `HardFault` handler.
+//!
+//! ## Watching for panics
+//!
+//! The `panic-serial-monitor` tool (also in the `decoder` directory) watches the serial output of a device, passing it
+//! through, and highlights panic reports in it, decoding frames, translating file names and symbolizing backtraces
+//! along the way (with `--paths` and `--elf`, like `panic-serial-decode`):
+//! ```sh
+//! stty -F /dev/ttyACM0 115200 raw -echo
+//! cargo run -p panic-serial-decoder --bin panic-serial-monitor -- --elf target/thumbv7em-none-eabihf/debug/firmware /dev/ttyACM0
+//! ```
+//! For hardware-in-the-loop tests, `--exit-on-panic` stops it once a report is complete, and `--timeout <SECONDS>`
+//! after the given time. The exit code is `101` if a panic was seen, `102` for a HardFault, and `0` otherwise.
+//!
 //! ## How does it work?
 //!
-//! The `impl_panic_handler` macro defines a mutable static `PANIC_PORT: Option<$your_type>`.
-//! When you call `share_serial_port_with_panic`, that option gets filled, and you get back `PANIC_PORT.as_mut().unwrap()`.
+//! The `impl_panic_handler` macro defines a static `PANIC_PORTS`, which holds a `PanicPort<$your_type>`.
+//! When you call `share_serial_port_with_panic`, the port is moved into it, and you get back a `SharedPort`, a handle which
+//! writes to the port (with `ufmt` or `core::fmt`), or gives access to it with `with`.
+//! Calling it again while the port is shared fails with an [`AlreadyShared`] error, which gives the port back.
 //!
-//! If a panic happens, the panic handler either just loops (if you never called `share_serial_port_with_panic`), or prints
-//! the panic info to the given port.
+//! If a panic happens, the panic handler either skips printing (if no port is shared at the time), or prints
+//! the panic info to the given port. If the last line written through the handle is unfinished (say, the panic
+//! happened in the middle of a `uwriteln!`), it finishes the line and prints `Config::SENTINEL` on one of its own
+//! first, so the report is easy to find:
+//! ```text
+//! temperature: 21
+//! !!! PANIC
+//! Panic at src/main.rs:91:9: attempt to subtract with overflow
+//! ```
 //! It does this in two steps:
 //! 1. call `port.flush()`
 //! 2. use `ufmt` (or `core::fmt`) to print the fragments.
 //!
+//! Afterwards it calls [`Config::after_panic`], which loops forever unless overridden.
+//! If printing itself panics, the nested panic does not touch the port again, but goes straight to `Config::after_panic`.
+//!
 //! Technically this works with *anything* that implements `ufmt::uWrite` and has a `flush()` method.
 //!
+//! ## Taking the port back
+//!
+//! The port stays with the panic handler for good, unless it is taken back, e.g. to hand it to a DMA driver:
+//! ```ignore
+//! let serial = share_serial_port_with_panic(serial).unwrap();
+//! // ...
+//! let serial = serial.take_back().unwrap();
+//! ```
+//! Until a port is shared again, panics are not printed (but `Config::after_panic` is still called). `replace` swaps
+//! in a different port in one step, returning the old one. Smaller changes, like setting the baud rate, can be made
+//! with `with`, without taking the port back.
+//!
+//! ## Using the port from interrupts
+//!
+//! The handle can be copied, to write to the port from interrupts as well as the main loop. The port is only used from
+//! one place at a time though: writes through the handle fail with `WriteError::Unavailable` while it is in use
+//! elsewhere. With the `critical-section` feature, every write happens within a critical section (see the
+//! `critical-section` crate), so that interrupts cannot get in between.
+
+//! A panic takes the port over, even if a write was interrupted by it. On multi-core chips like the RP2040 the
+//! `critical-section` feature is required: with it, a panic on one core waits for a write on the other core to finish.
+//! Without it, taking over an interrupted write is only sound on single core targets.
+//!
+//! ## Multiple ports
+//!
+//! The panic can be printed to several ports at once, e.g. a debug UART and an RS-485 bus.
+//! List the ports along with the name of the function sharing each one:
+//! ```ignore
+//! panic_serial::impl_panic_handler!(
+//!     share_debug_port_with_panic: DebugUart,
+//!     share_rs485_port_with_panic: Rs485Port,
+//! );
+//!
+//! let debug = share_debug_port_with_panic(debug).unwrap();
+//! let rs485 = share_rs485_port_with_panic(rs485).unwrap();
+//! ```
+//! A config goes after a semicolon: `impl_panic_handler!(a: A, b: B; PanicConfig)`.
+//! The ports are written in the given order. Ports which were never shared are skipped, and a failing port does not keep the others
+//! from being printed to.
+//!
+//! ## Other port types
+//!
+//! Most HALs implement `embedded_hal_nb::serial::Write<u8>` or `embedded_io::Write` for their UARTs, rather than `ufmt::uWrite`.
+//! With the `embedded-hal-nb` or `embedded-io` feature enabled, such ports can be used by wrapping them in
+//! `EmbeddedHalNb` or `EmbeddedIo` respectively:
+//! ```ignore
+//! panic_serial::impl_panic_handler!(panic_serial::EmbeddedIo<MyUart>);
+//!
+//! let serial = share_serial_port_with_panic(panic_serial::EmbeddedIo(uart)).unwrap();
+//! ```
+//! The adapters dereference to the wrapped port, so it can be used as before within `with`. Their `flush` blocks until
+//! the port is done transmitting.
+//!
+//! ## Debug probes
+//!
+//! When a debug probe is attached but no UART is wired, the report can go to the probe instead: through ARM semihosting
+//! (with the `semihosting` feature) or SEGGER RTT (with the `rtt` feature). The sinks take the place of the port type:
+//! ```ignore
+//! panic_serial::impl_panic_handler!(panic_serial::Rtt);
+//!
+//! let mut log = share_serial_port_with_panic(panic_serial::Rtt::take().unwrap()).unwrap();
+//! ```
+//! (or `panic_serial::Semihosting::new()`). The report is just like on a serial port, and the handle works for the rest
+//! of the output as well. A sink can also be listed next to a UART (see Multiple ports).
+//!
+//! `Rtt::take` hands out the one `Rtt` there is. It writes to up channel 0 of an RTT control block of its own, so other
+//! RTT crates (`rtt-target`, `defmt-rtt`) cannot be used in the same firmware. Read it with e.g. `probe-rs attach`. What
+//! does not fit into its buffer (`RTT_BUFFER_SIZE`, 1024 bytes) while the probe is not reading is cut off.
+//! `Semihosting` (Cortex-M only) writes to the console of the debugger, which stops the core for every write. Without a debugger attached,
+//! it raises a HardFault, so only share it while one is.
+//!
+//! ## Stuck ports
+//!
+//! If the port cannot transmit (say, the peripheral is disabled, or flow control holds it back), writing blocks forever, and
+//! the handler never gets to `Config::after_panic`. With `embedded-hal-nb`, `EmbeddedHalNbTimeout` bounds the wait:
+//! ```ignore
+//! // Gives up on a byte (or the flush) after 100000 polls of the port.
+//! panic_serial::impl_panic_handler!(panic_serial::EmbeddedHalNbTimeout<MyUart, 100_000>);
+//!
+//! let serial = share_serial_port_with_panic(panic_serial::EmbeddedHalNbTimeout::new(uart)).unwrap();
+//! ```
+//! Once the port gave up, every later write and flush fails with `TimeoutError::TimedOut` at once, so the handler waits for
+//! those 100000 polls only once, not for every part of the report. This also holds outside of the panic handler, until the
+//! port is `reset` (through `with`).
+//! Only `embedded-hal-nb` ports can be time-limited: `embedded-io` and plain `uWrite` ports block within their own writes.
+//!
 //! ## How unsafe is this?
 //!
 //! When you find out, please tell me.
 //!
 
 #![no_std]
-#![feature(panic_info_message)]
+#![cfg_attr(panic_info_message_legacy, feature(panic_info_message))]
+
+#[cfg(any(feature = "embedded-hal-nb", feature = "embedded-io"))]
+mod adapters;
+#[cfg(any(test, feature = "backtrace"))]
+mod backtrace;
+mod breadcrumbs;
+mod config;
+mod crc;
+#[cfg(any(test, feature = "cortex-m-rt"))]
+mod fault;
+mod firmware;
+mod formatter;
+pub mod frame;
+#[cfg(feature = "defmt")]
+mod log;
+mod port;
+mod record;
+mod regions;
+#[cfg(any(feature = "semihosting", feature = "rtt"))]
+mod sinks;
+mod timestamp;
+
+#[cfg(feature = "embedded-io")]
+pub use adapters::EmbeddedIo;
+#[cfg(feature = "embedded-hal-nb")]
+pub use adapters::{EmbeddedHalNb, EmbeddedHalNbTimeout, TimeoutError};
+#[cfg(feature = "breadcrumbs")]
+pub use breadcrumbs::_record_breadcrumb;
+#[doc(hidden)]
+pub use breadcrumbs::Entry as _BreadcrumbEntry;
+pub use breadcrumbs::{BREADCRUMB_CAPACITY, BREADCRUMB_COUNT};
+pub use config::{halt, Config, DefaultConfig};
+pub use firmware::gnu_build_id;
+pub use formatter::{DefaultFormatter, PanicFormatter};
+pub use port::{AlreadyShared, NotShared, PanicPort, SharedPort, WriteError};
+pub use record::{PanicRecord, RecordBuffer, RecordDisplay, FILE_CAPACITY, MESSAGE_CAPACITY};
+pub use regions::{Region, Regions};
+#[cfg(feature = "rtt")]
+pub use sinks::{Rtt, RTT_BUFFER_SIZE};
+#[cfg(feature = "semihosting")]
+pub use sinks::{Semihosting, SemihostingError};
 
-use core::fmt::Write;
-use core::panic::PanicInfo;
+use core::fmt::{Display, Write};
+use core::panic::{Location, PanicInfo};
 use ufmt::uWrite;
 
+#[doc(hidden)]
+pub use ufmt as _ufmt;
+
 struct WriteWrapper<'a, W: uWrite>(&'a mut W);
 
 impl<'a, W: uWrite> Write for WriteWrapper<'a, W> {
@@ -109,63 +510,284 @@ impl<'a, W: uWrite> Write for WriteWrapper<'a, W> {
 }
 
 /// Called internally by the panic handler.
-pub fn _print_panic<W: uWrite>(w: &mut W, info: &PanicInfo) {
+pub fn _print_panic<C: Config, F: PanicFormatter, W: uWrite>(
+    w: &mut W,
+    mid_line: bool,
+    info: &PanicInfo,
+) {
+    if mid_line {
+        break_line::<C, W>(w);
+    }
+    firmware::print_firmware_id::<C, W>(w);
+    F::format::<C, W, _>(w, info.location(), panic_message(info));
+    #[cfg(feature = "breadcrumbs")]
+    breadcrumbs::print_breadcrumbs::<C, W>(w, &breadcrumbs::BREADCRUMBS);
+    #[cfg(feature = "backtrace")]
+    backtrace::print_backtrace::<C, W>(w);
+}
+
+/// Called internally by the panic handler.
+///
+/// Returns whether the report was logged with `defmt`, in which case it is not printed to the ports.
+pub fn _log_panic<C: Config, const N: usize>(info: &PanicInfo, regions: &Regions<N>) -> bool {
+    #[cfg(feature = "defmt")]
+    log::log_panic::<C, N>(info, regions);
+    #[cfg(not(feature = "defmt"))]
+    let _ = (info, regions);
+    cfg!(feature = "defmt")
+}
+
+/// Called internally by the HardFault handler, like [`_log_panic`].
+#[cfg(feature = "cortex-m-rt")]
+pub fn _log_hard_fault<const N: usize>(
+    frame: &cortex_m_rt::ExceptionFrame,
+    regions: &Regions<N>,
+) -> bool {
+    #[cfg(feature = "defmt")]
+    log::log_hard_fault(frame, regions);
+    #[cfg(not(feature = "defmt"))]
+    let _ = (frame, regions);
+    cfg!(feature = "defmt")
+}
+
+/// Called internally by the HardFault handler.
+#[cfg(feature = "cortex-m-rt")]
+pub fn _print_hard_fault<C: Config, W: uWrite>(
+    w: &mut W,
+    mid_line: bool,
+    frame: &cortex_m_rt::ExceptionFrame,
+) {
+    if mid_line {
+        break_line::<C, W>(w);
+    }
+    firmware::print_firmware_id::<C, W>(w);
+    timestamp::print_timestamp::<C, W>(w);
+    let (registers, len) = fault::registers(frame);
+    fault::print_fault::<C, W>(w, &registers[..len]);
+}
+
+/// Finishes a line left partial by the firmware, and marks the start of the report with [`Config::SENTINEL`].
+fn break_line<C: Config, W: uWrite>(w: &mut W) {
+    _ = w.write_str(C::LINE_ENDING);
+    if !C::SENTINEL.is_empty() {
+        _ = w.write_str(C::SENTINEL);
+        _ = w.write_str(C::LINE_ENDING);
+    }
+}
+
+/// Called internally by the panic and HardFault handlers.
+pub fn _print_regions<C: Config, W: uWrite, const N: usize>(w: &mut W, regions: &Regions<N>) {
+    regions::print_regions::<C, W, N>(w, regions);
+}
+
+/// Returns the message of the panic, in whichever way the compiler provides it (see `build.rs`).
+#[cfg(not(any(panic_info_message_legacy, panic_info_display)))]
+pub(crate) fn panic_message<'a>(info: &'a PanicInfo<'a>) -> impl Display + 'a {
+    info.message()
+}
+
+#[cfg(panic_info_message_legacy)]
+pub(crate) fn panic_message<'a>(info: &'a PanicInfo<'a>) -> impl Display + 'a {
+    struct Message<'a>(Option<&'a core::fmt::Arguments<'a>>);
+
+    impl Display for Message<'_> {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            match self.0 {
+                Some(message) => f.write_fmt(*message),
+                None => Ok(()),
+            }
+        }
+    }
+
+    Message(info.message())
+}
+
+/// Stable compilers before 1.81 only provide the message as part of `PanicInfo`'s `Display` (including the location).
+#[cfg(panic_info_display)]
+pub(crate) fn panic_message<'a>(info: &'a PanicInfo<'a>) -> impl Display + 'a {
+    info
+}
+
+/// Prints the individual parts of a panic report.
+///
+/// This is what [`DefaultFormatter`] does. Takes the parts rather than a `PanicInfo`, so that it can be exercised without one.
+fn print_report<C: Config, W: uWrite, M: Display>(
+    w: &mut W,
+    location: Option<&Location>,
+    message: M,
+) {
+    if cfg!(feature = "binary") {
+        frame::write_panic(w, location, message, C::LINE_ENDING);
+        return;
+    }
+
     let location_feature = cfg!(feature = "location");
     let message_feature = cfg!(feature = "message");
 
     if location_feature {
-        if let Some(location) = info.location() {
-            _ = ufmt::uwrite!(
-                w,
-                "Panic at {}:{}:{}",
-                location.file(),
-                location.line(),
-                location.column()
-            );
-            _ = w.write_str(if message_feature { ": " } else { "\r\n" });
+        if let Some(location) = location {
+            if cfg!(feature = "file-hash") {
+                _ = ufmt::uwrite!(
+                    w,
+                    "{}#{}:{}:{}",
+                    C::PREFIX,
+                    frame::Hex(frame::file_hash(location.file())),
+                    location.line(),
+                    location.column()
+                );
+            } else {
+                _ = ufmt::uwrite!(
+                    w,
+                    "{}{}:{}:{}",
+                    C::PREFIX,
+                    location.file(),
+                    location.line(),
+                    location.column()
+                );
+            }
+            _ = w.write_str(if message_feature {
+                C::SEPARATOR
+            } else {
+                C::LINE_ENDING
+            });
         }
     }
 
     if message_feature {
-        let message = info.message();
-        if let Some(message) = message.as_str() {
-            _ = w.write_str(message);
-        }
-        _ = w.write_str("\r\n");
+        // Goes through `core::fmt`, so that messages with arguments are rendered as well.
+        _ = write!(WriteWrapper(w), "{}", message);
+        _ = w.write_str(C::LINE_ENDING);
     }
 
     if !message_feature && !location_feature {
-        _ = ufmt::uwriteln!(w, "PANIC !\r");
+        _ = w.write_str(C::FALLBACK_MESSAGE);
+        _ = w.write_str(C::LINE_ENDING);
     }
 }
 
 /// Implements the panic handler. You need to call this for the package to work.
 ///
 /// This macro defines the panic handler, as well as a function called `share_serial_port_with_panic`.
-/// That function takes an argument of the given `$type` and returns a `&'static mut $type`.
+/// That function takes an argument of the given `$type` and returns a [`SharedPort`] to keep using it,
+/// or an [`AlreadyShared`] error if a port is already shared.
+///
+/// Optionally a second argument can be given: a type implementing [`Config`], which customizes the handler.
+/// A third argument replaces the layout of the report by a [`PanicFormatter`].
+///
+/// To print the panic to several ports, list them together with the name of the function sharing each one
+/// (optionally followed by `; $config` or `; $config, $formatter`):
+/// ```ignore
+/// panic_serial::impl_panic_handler!(
+///     share_debug_port_with_panic: DebugUart,
+///     share_rs485_port_with_panic: Rs485Port,
+/// );
+/// ```
+/// The panic is then printed to every shared port, in the given order. Ports which were not shared are skipped,
+/// and errors on one port do not stop printing to the next one.
+///
+/// The macro also defines a function called `take_last_panic`, which returns the panic record that
+/// was kept across the last reset (see [`RecordBuffer`]), if the config provides a buffer for it.
+///
+/// Finally, it defines `dump_on_panic`, which takes a [`Region`] of memory to dump after the report,
+/// and gives it back if all [`Config::MAX_REGIONS`] slots are taken.
 ///
 #[macro_export]
 macro_rules! impl_panic_handler {
-    ($type:ty) => {
-        static mut PANIC_PORT: Option<$type> = None;
+    (@ports [$($share:ident: $type:ty),+] $config:ty, $formatter:ty) => {
+        struct PanicPorts {
+            $($share: $crate::PanicPort<$type>,)+
+        }
+
+        static PANIC_PORTS: PanicPorts = PanicPorts {
+            $($share: $crate::PanicPort::new(),)+
+        };
+
+        static PANIC_REGIONS: $crate::Regions<{ <$config as $crate::Config>::MAX_REGIONS }> =
+            $crate::Regions::new();
 
         #[inline(never)]
         #[panic_handler]
         fn panic(info: &::core::panic::PanicInfo) -> ! {
-            if let Some(panic_port) = unsafe { PANIC_PORT.as_mut() } {
-                _ = panic_port.flush();
-                ::panic_serial::_print_panic(panic_port, info);
+            if let Some(record) = <$config as $crate::Config>::record() {
+                record.store(info);
+            }
+            if !$crate::_log_panic::<$config, _>(info, &PANIC_REGIONS) {
+                $(
+                    if let Some((panic_port, mid_line)) = PANIC_PORTS.$share._enter_panic() {
+                        _ = panic_port.flush();
+                        $crate::_print_panic::<$config, $formatter, _>(panic_port, mid_line, info);
+                        $crate::_print_regions::<$config, _, _>(panic_port, &PANIC_REGIONS);
+                    }
+                )+
             }
-            loop {
-                ::core::sync::atomic::compiler_fence(::core::sync::atomic::Ordering::SeqCst);
+            <$config as $crate::Config>::after_panic(info)
+        }
+
+        $crate::_impl_hard_fault_handler!(PANIC_PORTS PANIC_REGIONS [$($share),+] $config);
+
+        $(
+            pub fn $share(port: $type) -> Result<$crate::SharedPort<$type>, $crate::AlreadyShared<$type>> {
+                PANIC_PORTS.$share.share(port)
             }
+        )+
+
+        #[allow(dead_code)]
+        pub fn take_last_panic() -> Option<$crate::PanicRecord> {
+            <$config as $crate::Config>::record().and_then($crate::RecordBuffer::take)
         }
 
-        pub fn share_serial_port_with_panic(port: $type) -> &'static mut $type {
-            unsafe {
-                PANIC_PORT = Some(port);
-                PANIC_PORT.as_mut().unwrap()
+        #[allow(dead_code)]
+        pub fn dump_on_panic(region: $crate::Region) -> Result<(), $crate::Region> {
+            PANIC_REGIONS.add(region)
+        }
+    };
+    ($type:ty) => {
+        $crate::impl_panic_handler!($type, $crate::DefaultConfig);
+    };
+    ($type:ty, $config:ty) => {
+        $crate::impl_panic_handler!($type, $config, $crate::DefaultFormatter);
+    };
+    ($type:ty, $config:ty, $formatter:ty) => {
+        $crate::impl_panic_handler!(@ports [share_serial_port_with_panic: $type] $config, $formatter);
+    };
+    ($($share:ident: $type:ty),+ $(,)?) => {
+        $crate::impl_panic_handler!($($share: $type),+ ; $crate::DefaultConfig);
+    };
+    ($($share:ident: $type:ty),+ ; $config:ty) => {
+        $crate::impl_panic_handler!($($share: $type),+ ; $config, $crate::DefaultFormatter);
+    };
+    ($($share:ident: $type:ty),+ ; $config:ty, $formatter:ty) => {
+        $crate::impl_panic_handler!(@ports [$($share: $type),+] $config, $formatter);
+    };
+}
+
+#[cfg(feature = "cortex-m-rt")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! _impl_hard_fault_handler {
+    ($ports:ident $regions:ident [$($share:ident),+] $config:ty) => {
+        #[::cortex_m_rt::exception]
+        unsafe fn HardFault(frame: &::cortex_m_rt::ExceptionFrame) -> ! {
+            if !$crate::_log_hard_fault(frame, &$regions) {
+                $(
+                    if let Some((panic_port, mid_line)) = $ports.$share._enter_panic() {
+                        _ = panic_port.flush();
+                        $crate::_print_hard_fault::<$config, _>(panic_port, mid_line, frame);
+                        $crate::_print_regions::<$config, _, _>(panic_port, &$regions);
+                    }
+                )+
             }
+            <$config as $crate::Config>::after_hard_fault(frame)
         }
     };
 }
+
+#[cfg(not(feature = "cortex-m-rt"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! _impl_hard_fault_handler {
+    ($($tt:tt)*) => {};
+}
+
+#[cfg(test)]
+mod tests;
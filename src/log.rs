@@ -0,0 +1,142 @@
+//! Logging the report with `defmt`, with the `defmt` feature.
+//!
+//! The report goes out as `defmt` error frames over the global logger (e.g. `defmt-rtt`), instead of being printed
+//! to the ports, so that it shows up along with the rest of the firmware's log. The firmware id and the timestamp
+//! are left out: `defmt` tells the firmware apart by its ELF file, and has timestamps of its own. A
+//! [`PanicFormatter`](crate::PanicFormatter) is not used either, since it writes text to a port: the report is
+//! always logged in the default layout.
+
+use crate::{panic_message, Config, Regions};
+use core::panic::PanicInfo;
+use portable_atomic::{AtomicBool, Ordering};
+
+/// Set once a report is being logged, so that a panic within the logger (say, because it was already in use when
+/// the firmware panicked) does not end up in it again.
+static LOGGING: AtomicBool = AtomicBool::new(false);
+
+/// Number of values logged per frame, for backtraces and memory regions.
+const CHUNK: usize = 16;
+
+/// Logs the panic report, along with the breadcrumbs, backtrace and regions.
+pub(crate) fn log_panic<C: Config, const N: usize>(info: &PanicInfo, regions: &Regions<N>) {
+    if LOGGING.swap(true, Ordering::Acquire) {
+        return;
+    }
+    let location = info.location().filter(|_| cfg!(feature = "location"));
+    match (location, cfg!(feature = "message")) {
+        (Some(location), true) => defmt::error!(
+            "Panic at {=str}:{=u32}:{=u32}: {}",
+            location.file(),
+            location.line(),
+            location.column(),
+            defmt::Display2Format(&panic_message(info))
+        ),
+        (Some(location), false) => defmt::error!(
+            "Panic at {=str}:{=u32}:{=u32}",
+            location.file(),
+            location.line(),
+            location.column()
+        ),
+        (None, true) => defmt::error!("{}", defmt::Display2Format(&panic_message(info))),
+        (None, false) => defmt::error!("{=str}", C::FALLBACK_MESSAGE),
+    }
+    #[cfg(feature = "breadcrumbs")]
+    crate::breadcrumbs::BREADCRUMBS.for_each(|breadcrumb| {
+        defmt::error!("Breadcrumb: {=str}", breadcrumb);
+    });
+    #[cfg(feature = "backtrace")]
+    crate::backtrace::scan::<C>(|addresses| {
+        chunks(addresses.map(|address| address as u32), |_, addresses| {
+            defmt::error!("Backtrace:{}", Addresses(addresses))
+        })
+    });
+    log_regions(regions);
+}
+
+/// Logs the registers of a HardFault, along with the regions.
+#[cfg(feature = "cortex-m-rt")]
+pub(crate) fn log_hard_fault<const N: usize>(
+    frame: &cortex_m_rt::ExceptionFrame,
+    regions: &Regions<N>,
+) {
+    if LOGGING.swap(true, Ordering::Acquire) {
+        return;
+    }
+    let (registers, len) = crate::fault::registers(frame);
+    defmt::error!(
+        "HardFault: r0={=u32:#010x} r1={=u32:#010x} r2={=u32:#010x} r3={=u32:#010x} r12={=u32:#010x} lr={=u32:#010x} pc={=u32:#010x} xpsr={=u32:#010x}",
+        registers[0],
+        registers[1],
+        registers[2],
+        registers[3],
+        registers[4],
+        registers[5],
+        registers[6],
+        registers[7]
+    );
+    if len == registers.len() {
+        defmt::error!(
+            "HardFault: cfsr={=u32:#010x} hfsr={=u32:#010x} mmfar={=u32:#010x} bfar={=u32:#010x}",
+            registers[8],
+            registers[9],
+            registers[10],
+            registers[11]
+        );
+    }
+    log_regions(regions);
+}
+
+/// Logs every region: its name, address and length, followed by its bytes.
+fn log_regions<const N: usize>(regions: &Regions<N>) {
+    for region in regions.iter() {
+        defmt::error!(
+            "Region {=str} at {=u32:#010x} ({=usize} bytes)",
+            region.name(),
+            region.address(),
+            region.len()
+        );
+        chunks(region.bytes(), |offset, bytes| {
+            defmt::error!(
+                "{=u32:#010x}: {=[u8]:02x}",
+                region.address().wrapping_add(offset as u32),
+                bytes
+            )
+        });
+    }
+}
+
+/// Logs addresses in hex, separated by spaces.
+///
+/// `defmt` has display hints for slices of bytes, but not for other slices, so each address is written on its own.
+#[cfg(feature = "backtrace")]
+struct Addresses<'a>(&'a [u32]);
+
+#[cfg(feature = "backtrace")]
+impl defmt::Format for Addresses<'_> {
+    fn format(&self, f: defmt::Formatter<'_>) {
+        for address in self.0 {
+            defmt::write!(f, " {=u32:#010x}", *address);
+        }
+    }
+}
+
+/// Passes the values to `f` in chunks of up to [`CHUNK`], along with the offset of each chunk.
+///
+/// Logging a value at a time would take a frame each, and there is no memory to collect them all.
+fn chunks<T: Copy + Default>(values: impl Iterator<Item = T>, mut f: impl FnMut(usize, &[T])) {
+    let mut chunk = [T::default(); CHUNK];
+    let mut offset = 0;
+    let mut len = 0;
+    for value in values {
+        chunk[len] = value;
+        len += 1;
+        if len == CHUNK {
+            f(offset, &chunk);
+            offset += CHUNK;
+            len = 0;
+        }
+    }
+    if len > 0 {
+        f(offset, &chunk[..len]);
+    }
+}
@@ -0,0 +1,371 @@
+use core::cell::UnsafeCell;
+use core::fmt::Debug;
+use core::mem::MaybeUninit;
+use portable_atomic::{AtomicBool, AtomicU8, Ordering};
+use ufmt::uWrite;
+
+/// No port is shared (yet, or any more).
+const UNSET: u8 = 0;
+/// The port is being moved in or out.
+const SHARING: u8 = 1;
+/// The port is available to the panic handler.
+const SHARED: u8 = 2;
+/// The panic handler is using the port.
+const PANICKING: u8 = 3;
+/// The port is used through a [`SharedPort`], so the panic handler may interrupt a write.
+const WRITING: u8 = 4;
+
+/// Holds the port shared with the panic handler.
+///
+/// [`impl_panic_handler`](crate::impl_panic_handler) defines a static of this type. The state of the port
+/// is tracked atomically, so that:
+/// - only one port is shared at a time (see [`AlreadyShared`]),
+/// - the port is only used from one place at a time, see [`SharedPort`],
+/// - the port can be taken back (see [`SharedPort::take_back`]), after which panics are not printed
+///   until a port is shared again,
+/// - the panic handler knows whether the last line written through the [`SharedPort`] is complete,
+/// - a panic while the handler is printing (for example within the port's `write_str`) does not use the
+///   port again, but goes straight to [`Config::after_panic`](crate::Config::after_panic).
+pub struct PanicPort<T> {
+    state: AtomicU8,
+    /// Whether the last byte written through the `SharedPort` was not a line break.
+    mid_line: AtomicBool,
+    port: UnsafeCell<MaybeUninit<T>>,
+}
+
+// Access to `port` is guarded by `state`.
+unsafe impl<T: Send> Sync for PanicPort<T> {}
+
+impl<T> PanicPort<T> {
+    /// Creates an empty `PanicPort`.
+    pub const fn new() -> Self {
+        PanicPort {
+            state: AtomicU8::new(UNSET),
+            mid_line: AtomicBool::new(false),
+            port: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    /// Moves the port in, returning a handle through which it can still be used outside of the panic handler.
+    ///
+    /// Fails if a port is already shared.
+    pub fn share(&'static self, port: T) -> Result<SharedPort<T>, AlreadyShared<T>> {
+        if self
+            .state
+            .compare_exchange(UNSET, SHARING, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            return Err(AlreadyShared(port));
+        }
+        // SAFETY: the `SHARING` state gives us exclusive access, and the slot is empty in the `UNSET` state.
+        unsafe { (*self.port.get()).write(port) };
+        self.mid_line.store(false, Ordering::Relaxed);
+        self.state.store(SHARED, Ordering::Release);
+        Ok(SharedPort(self))
+    }
+
+    /// Calls `f` with the port, unless none is shared, or it is in use.
+    fn with<R>(&self, f: impl FnOnce(&mut T) -> R) -> Option<R> {
+        self.state
+            .compare_exchange(SHARED, WRITING, Ordering::Acquire, Ordering::Relaxed)
+            .ok()?;
+        // SAFETY: the `WRITING` state gives us exclusive access, except for the panic handler, which
+        // never returns to us.
+        let result = f(unsafe { (*self.port.get()).assume_init_mut() });
+        self.state.store(SHARED, Ordering::Release);
+        Some(result)
+    }
+
+    /// Switches from `SHARED` to `SHARING`, unless no port is shared, or it is in use.
+    fn begin_move(&self) -> bool {
+        self.state
+            .compare_exchange(SHARED, SHARING, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    /// Called internally by the panic handler.
+    ///
+    /// Returns the port, unless none is shared, or the handler is already using it. Along with it comes whether
+    /// the last line written through the [`SharedPort`] is unfinished, or a write was interrupted.
+    ///
+    /// With the `critical-section` feature, this waits for a write through the [`SharedPort`] on another core to
+    /// finish. Without it, a write is only ever interrupted on the same core, which is only sound on single core
+    /// targets.
+    #[doc(hidden)]
+    #[allow(clippy::mut_from_ref)]
+    pub fn _enter_panic(&self) -> Option<(&mut T, bool)> {
+        #[cfg(feature = "critical-section")]
+        return critical_section::with(|_| self.take_for_panic());
+        #[cfg(not(feature = "critical-section"))]
+        self.take_for_panic()
+    }
+
+    /// Switches to `PANICKING`, see [`_enter_panic`](PanicPort::_enter_panic).
+    #[allow(clippy::mut_from_ref)]
+    fn take_for_panic(&self) -> Option<(&mut T, bool)> {
+        let state = self
+            .state
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |state| {
+                matches!(state, SHARED | WRITING).then_some(PANICKING)
+            })
+            .ok()?;
+        // SAFETY: the port was written before the state became `SHARED`. If it is used through a `SharedPort`,
+        // that use is on this core (writes on other cores are waited for within the critical section, or there
+        // are none), and it is never continued, since the panic handler does not return.
+        Some((
+            unsafe { (*self.port.get()).assume_init_mut() },
+            state == WRITING || self.mid_line.load(Ordering::Relaxed),
+        ))
+    }
+}
+
+impl<T> Default for PanicPort<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Handle to the port shared with the panic handler, returned by the sharing function.
+///
+/// Writes go to the port through [`ufmt::uWrite`] or [`core::fmt::Write`], anything else through
+/// [`with`](SharedPort::with). The handle keeps track of whether the last byte written was a line break, so
+/// that the panic handler can finish a partial line before printing (see [`Config::SENTINEL`](crate::Config::SENTINEL)).
+/// Writes within `with` are not tracked.
+///
+/// The handle can be copied, e.g. to use the port from an interrupt. The port is only used from one place at a
+/// time though, elsewhere it is [unavailable](WriteError::Unavailable). With the `critical-section` feature,
+/// every use of the port happens within a critical section, so that an interrupt cannot find it in use
+/// (on single core targets), and a panic on another core waits for the write to finish.
+///
+/// Without the `critical-section` feature, a panic takes the port over even in the middle of a write, which
+/// is only sound on single core targets.
+pub struct SharedPort<T: 'static>(&'static PanicPort<T>);
+
+impl<T> SharedPort<T> {
+    /// Calls `f` with the port, e.g. to read from it or change its baud rate.
+    ///
+    /// Returns `None` if the port is in use elsewhere (for example if `with` is called within `f`), or it was
+    /// taken back.
+    pub fn with<R>(&self, f: impl FnOnce(&mut T) -> R) -> Option<R> {
+        #[cfg(feature = "critical-section")]
+        return critical_section::with(|_| self.0.with(f));
+        #[cfg(not(feature = "critical-section"))]
+        self.0.with(f)
+    }
+
+    /// Moves the port back out, e.g. to hand it to a DMA driver. Until a port is shared again, panics
+    /// are not printed.
+    ///
+    /// Fails if the port is in use, or was already taken back.
+    pub fn take_back(self) -> Result<T, NotShared<Self>> {
+        if !self.0.begin_move() {
+            return Err(NotShared(self));
+        }
+        // SAFETY: the port was written when it was shared, and the `SHARING` state gives us exclusive access.
+        let port = unsafe { (*self.0.port.get()).assume_init_read() };
+        self.0.state.store(UNSET, Ordering::Release);
+        Ok(port)
+    }
+
+    /// Swaps the shared port for `new`, returning the old one.
+    ///
+    /// Like [`take_back`](SharedPort::take_back) followed by sharing `new`, but in one step, so no other port
+    /// can be shared in between. The handle (and its copies) now refer to `new`.
+    pub fn replace(&self, new: T) -> Result<T, NotShared<T>> {
+        if !self.0.begin_move() {
+            return Err(NotShared(new));
+        }
+        // SAFETY: as in `take_back`.
+        let old = unsafe { core::ptr::replace((*self.0.port.get()).as_mut_ptr(), new) };
+        self.0.mid_line.store(false, Ordering::Relaxed);
+        self.0.state.store(SHARED, Ordering::Release);
+        Ok(old)
+    }
+}
+
+impl<T> Clone for SharedPort<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for SharedPort<T> {}
+
+impl<T> Debug for SharedPort<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("SharedPort(..)")
+    }
+}
+
+impl<T: uWrite> uWrite for SharedPort<T> {
+    type Error = WriteError<T::Error>;
+
+    fn write_str(&mut self, s: &str) -> Result<(), Self::Error> {
+        self.with(|port| {
+            let result = port.write_str(s);
+            if let Some(&last) = s.as_bytes().last() {
+                self.0.mid_line.store(last != b'\n', Ordering::Relaxed);
+            }
+            result
+        })
+        .ok_or(WriteError::Unavailable)?
+        .map_err(WriteError::Port)
+    }
+}
+
+impl<T: uWrite> core::fmt::Write for SharedPort<T> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        uWrite::write_str(self, s).map_err(|_| core::fmt::Error)
+    }
+}
+
+/// Error of writing to a [`SharedPort`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WriteError<E> {
+    /// The port reported an error.
+    Port(E),
+    /// The port is in use elsewhere, or was taken back.
+    Unavailable,
+}
+
+/// Returned when sharing a port while another one was already shared. Contains the rejected port.
+pub struct AlreadyShared<T>(pub T);
+
+impl<T> AlreadyShared<T> {
+    /// Returns the port that could not be shared.
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Debug for AlreadyShared<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("AlreadyShared(..)")
+    }
+}
+
+/// Returned when taking back or replacing a port which is in use, or was already taken back. Contains the
+/// given argument.
+pub struct NotShared<T>(pub T);
+
+impl<T> NotShared<T> {
+    /// Returns the argument which was rejected.
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Debug for NotShared<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("NotShared(..)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::tests::Buffer;
+    use std::boxed::Box;
+    use std::string::String;
+
+    fn leak<T>() -> &'static PanicPort<T> {
+        Box::leak(Box::new(PanicPort::new()))
+    }
+
+    #[test]
+    fn panic_without_port() {
+        let port = leak::<u8>();
+        assert!(port._enter_panic().is_none());
+    }
+
+    #[test]
+    fn second_share_is_rejected() {
+        let port = leak::<u8>();
+        assert_eq!(port.share(1).unwrap().with(|port| *port), Some(1));
+        assert_eq!(port.share(2).unwrap_err().into_inner(), 2);
+    }
+
+    #[test]
+    fn nested_panic_does_not_reenter() {
+        let port = leak::<u8>();
+        port.share(1).unwrap().with(|port| *port = 5);
+        assert_eq!(port._enter_panic().map(|(port, _)| *port), Some(5));
+        assert!(port._enter_panic().is_none());
+    }
+
+    #[test]
+    fn writes_through_handle() {
+        let port = leak::<Buffer>();
+        let mut shared = port.share(Buffer(String::new())).unwrap();
+        ufmt::uwrite!(shared, "value {}", 1).unwrap();
+        core::fmt::Write::write_fmt(&mut shared, format_args!(" and {:?}", Some(2))).unwrap();
+        ufmt::uwriteln!(shared, "").unwrap();
+        let (buffer, mid_line) = port._enter_panic().unwrap();
+        assert_eq!(buffer.0, "value 1 and Some(2)\n");
+        assert!(!mid_line);
+    }
+
+    #[test]
+    fn tracks_unfinished_lines() {
+        let port = leak::<Buffer>();
+        let mut shared = port.share(Buffer(String::new())).unwrap();
+        ufmt::uwrite!(shared, "state {}", 3).unwrap();
+        assert!(port.mid_line.load(Ordering::Relaxed));
+        ufmt::uwriteln!(shared, "\r").unwrap();
+        assert!(!port.mid_line.load(Ordering::Relaxed));
+        shared.write_str("").unwrap();
+        assert!(!port.mid_line.load(Ordering::Relaxed));
+
+        shared.write_str("partial").unwrap();
+        let (buffer, mid_line) = port._enter_panic().unwrap();
+        assert_eq!(buffer.0, "state 3\r\npartial");
+        assert!(mid_line);
+    }
+
+    #[test]
+    fn port_is_used_from_one_place_at_a_time() {
+        let port = leak::<Buffer>();
+        let shared = port.share(Buffer(String::new())).unwrap();
+        let mut copy = shared;
+        shared.with(|_| {
+            assert_eq!(copy.write_str("x"), Err(WriteError::Unavailable));
+            assert!(copy.take_back().is_err());
+        });
+        assert_eq!(copy.write_str("y"), Ok(()));
+    }
+
+    #[test]
+    fn panic_interrupts_write() {
+        let port = leak::<u8>();
+        port.share(1).unwrap().with(|_| {
+            let (port, mid_line) = port._enter_panic().unwrap();
+            assert_eq!((*port, mid_line), (1, true));
+        });
+    }
+
+    #[test]
+    fn taken_back_port_is_not_printed_to() {
+        let port = leak::<u8>();
+        let shared = port.share(1).unwrap();
+        shared.with(|port| *port = 5);
+        assert_eq!(shared.take_back().unwrap(), 5);
+        assert!(port._enter_panic().is_none());
+        assert_eq!(shared.with(|port| *port), None);
+        assert!(shared.take_back().is_err());
+
+        assert_eq!(port.share(2).unwrap().with(|port| *port), Some(2));
+        assert_eq!(port._enter_panic().map(|(port, _)| *port), Some(2));
+    }
+
+    #[test]
+    fn replaced_port_is_printed_to() {
+        let port = leak::<u8>();
+        let shared = port.share(1).unwrap();
+        assert_eq!(shared.replace(2).unwrap(), 1);
+        assert_eq!(shared.with(|port| *port), Some(2));
+        assert_eq!(port._enter_panic().map(|(port, _)| *port), Some(2));
+        assert_eq!(shared.replace(3).unwrap_err().into_inner(), 3);
+    }
+}
@@ -0,0 +1,410 @@
+//! Keeping a compact record of the last panic across resets.
+
+use crate::crc::Crc16;
+use crate::frame::{self, Hex};
+use crate::Config;
+use core::cell::UnsafeCell;
+use core::fmt::{Display, Write};
+use core::marker::PhantomData;
+use core::mem::MaybeUninit;
+use core::panic::{Location, PanicInfo};
+use core::ptr;
+use ufmt::uWrite;
+
+/// Marks a record as valid. Spells "PNIC".
+const MAGIC: u32 = 0x504e_4943;
+
+/// Maximum number of bytes kept of the file name. Longer paths keep their end.
+pub const FILE_CAPACITY: usize = 48;
+
+/// Maximum number of bytes kept of the panic message. Longer messages are cut off.
+pub const MESSAGE_CAPACITY: usize = 80;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Raw {
+    magic: u32,
+    checksum: u16,
+    file_len: u8,
+    message_len: u8,
+    line: u32,
+    column: u32,
+    file: [u8; FILE_CAPACITY],
+    message: [u8; MESSAGE_CAPACITY],
+}
+
+impl Raw {
+    fn checksum(&self) -> u16 {
+        let mut crc = Crc16::new();
+        crc.update(&[self.file_len, self.message_len]);
+        crc.update(&self.line.to_le_bytes());
+        crc.update(&self.column.to_le_bytes());
+        crc.update(&self.file[..(self.file_len as usize).min(FILE_CAPACITY)]);
+        crc.update(&self.message[..(self.message_len as usize).min(MESSAGE_CAPACITY)]);
+        crc.finish()
+    }
+}
+
+/// Storage for a [`PanicRecord`], meant to be placed in RAM that is not initialized on startup.
+///
+/// Declare it as a static in such a section, and return it from [`Config::record`](crate::Config::record):
+/// ```ignore
+/// // `.uninit` is kept by cortex-m-rt's linker script, on AVR use `.noinit` instead.
+/// #[link_section = ".uninit.PANIC_RECORD"]
+/// static PANIC_RECORD: panic_serial::RecordBuffer = panic_serial::RecordBuffer::uninit();
+/// ```
+///
+/// The panic handler then stores a record before printing, and after the next reset `take_last_panic`
+/// (defined by [`impl_panic_handler`](crate::impl_panic_handler)) hands it out.
+///
+/// Which parts are recorded follows the feature flags: the location is only kept with `location`, the message only with `message`.
+pub struct RecordBuffer(UnsafeCell<MaybeUninit<Raw>>);
+
+// The buffer is only written by the panic handler, and read/cleared through `take`.
+unsafe impl Sync for RecordBuffer {}
+
+impl RecordBuffer {
+    /// Creates a buffer without initializing it, so whatever survived the last reset is kept.
+    pub const fn uninit() -> Self {
+        RecordBuffer(UnsafeCell::new(MaybeUninit::uninit()))
+    }
+
+    /// Stores a record of the given panic in the buffer, replacing any previous one.
+    pub fn store(&self, info: &PanicInfo) {
+        #[cfg(feature = "message")]
+        let message = crate::panic_message(info);
+        #[cfg(not(feature = "message"))]
+        let message = "";
+        self.store_parts(info.location(), message);
+    }
+
+    pub(crate) fn store_parts<M: Display>(&self, location: Option<&Location>, message: M) {
+        let mut raw = Raw {
+            magic: 0,
+            checksum: 0,
+            file_len: 0,
+            message_len: 0,
+            line: 0,
+            column: 0,
+            file: [0; FILE_CAPACITY],
+            message: [0; MESSAGE_CAPACITY],
+        };
+
+        if cfg!(feature = "location") {
+            if let Some(location) = location {
+                let file = location.file();
+                if cfg!(feature = "file-hash") {
+                    // Kept as printed by the panic handler, hashed from the whole path.
+                    raw.file[0] = b'#';
+                    raw.file[1..9].copy_from_slice(&Hex(frame::file_hash(file)).digits());
+                    raw.file_len = 9;
+                } else {
+                    let mut start = file.len().saturating_sub(FILE_CAPACITY);
+                    while !file.is_char_boundary(start) {
+                        start += 1;
+                    }
+                    let file = &file.as_bytes()[start..];
+                    raw.file[..file.len()].copy_from_slice(file);
+                    raw.file_len = file.len() as u8;
+                }
+                raw.line = location.line();
+                raw.column = location.column();
+            }
+        }
+
+        if cfg!(feature = "message") {
+            let mut truncate = Truncate::new(&mut raw.message);
+            _ = write!(truncate, "{}", message);
+            raw.message_len = truncate.len as u8;
+        }
+
+        raw.checksum = raw.checksum();
+        raw.magic = MAGIC;
+
+        // Volatile, because nothing in this program run will ever read it again.
+        unsafe { ptr::write_volatile(self.0.get(), MaybeUninit::new(raw)) }
+    }
+
+    /// Returns the stored record, if there is a valid one, and clears the buffer.
+    pub fn take(&self) -> Option<PanicRecord> {
+        let raw = self.0.get() as *mut Raw;
+        // SAFETY: after a cold boot, the memory was never written by any run of the program, and to the Rust
+        // abstract machine it is uninitialized. Reading it is only sound under an assumption the language does not
+        // guarantee: that, with the buffer in a section the startup code leaves alone, a volatile read through a raw
+        // pointer returns whatever bits the RAM holds, like a read of a device register would. Every bit pattern is a
+        // valid `Raw`, and the magic value and checksum tell whether it is a record we stored.
+        unsafe {
+            if ptr::read_volatile(ptr::addr_of!((*raw).magic)) != MAGIC {
+                return None;
+            }
+            ptr::write_volatile(ptr::addr_of_mut!((*raw).magic), 0);
+            let raw = ptr::read_volatile(raw);
+            if raw.checksum != raw.checksum()
+                || raw.file_len as usize > FILE_CAPACITY
+                || raw.message_len as usize > MESSAGE_CAPACITY
+            {
+                return None;
+            }
+            let record = PanicRecord(raw);
+            if core::str::from_utf8(record.file_bytes()).is_err()
+                || core::str::from_utf8(record.message_bytes()).is_err()
+            {
+                return None;
+            }
+            Some(record)
+        }
+    }
+}
+
+/// Panic information recovered from a [`RecordBuffer`].
+///
+/// Prints like the panic handler would through [`display`](PanicRecord::display).
+#[derive(Clone, Copy)]
+pub struct PanicRecord(Raw);
+
+impl PanicRecord {
+    /// File in which the panic happened, or `None` if no location was recorded.
+    /// Long paths are cut off at the start. With the `file-hash` feature, this is the hash of the file name,
+    /// as printed by the panic handler (e.g. `#1660407c`).
+    pub fn file(&self) -> Option<&str> {
+        if self.0.line == 0 {
+            return None;
+        }
+        core::str::from_utf8(self.file_bytes()).ok()
+    }
+
+    /// Line on which the panic happened (`0` if no location was recorded).
+    pub fn line(&self) -> u32 {
+        self.0.line
+    }
+
+    /// Column at which the panic happened (`0` if no location was recorded).
+    pub fn column(&self) -> u32 {
+        self.0.column
+    }
+
+    /// The panic message, cut off after [`MESSAGE_CAPACITY`] bytes.
+    pub fn message(&self) -> &str {
+        core::str::from_utf8(self.message_bytes()).unwrap_or_default()
+    }
+
+    fn file_bytes(&self) -> &[u8] {
+        &self.0.file[..self.0.file_len as usize]
+    }
+
+    fn message_bytes(&self) -> &[u8] {
+        &self.0.message[..self.0.message_len as usize]
+    }
+}
+
+impl PanicRecord {
+    /// Prints the record like the panic handler with the config `C` would (without a line break), both via
+    /// `ufmt` and `core::fmt`:
+    /// ```ignore
+    /// ufmt::uwriteln!(serial, "Previous {}\r", record.display::<PanicConfig>()).unwrap();
+    /// ```
+    pub fn display<C: Config>(&self) -> RecordDisplay<'_, C> {
+        RecordDisplay(self, PhantomData)
+    }
+}
+
+/// Prints a [`PanicRecord`] with the prefix, separator and fallback message of `C`, see [`PanicRecord::display`].
+pub struct RecordDisplay<'a, C>(&'a PanicRecord, PhantomData<fn() -> C>);
+
+impl<C: Config> RecordDisplay<'_, C> {
+    /// Writes the record, for both `uDisplay` and `Display`.
+    fn write<W: uWrite + ?Sized>(&self, w: &mut W) -> Result<(), W::Error> {
+        let record = self.0;
+        if let Some(file) = record.file() {
+            ufmt::uwrite!(
+                w,
+                "{}{}:{}:{}",
+                C::PREFIX,
+                file,
+                record.line(),
+                record.column()
+            )?;
+            if !record.message().is_empty() {
+                w.write_str(C::SEPARATOR)?;
+            }
+        } else if record.message().is_empty() {
+            return w.write_str(C::FALLBACK_MESSAGE);
+        }
+        w.write_str(record.message())
+    }
+}
+
+impl<C: Config> ufmt::uDisplay for RecordDisplay<'_, C> {
+    fn fmt<W: uWrite + ?Sized>(&self, f: &mut ufmt::Formatter<'_, W>) -> Result<(), W::Error> {
+        self.write(f)
+    }
+}
+
+impl<C: Config> Display for RecordDisplay<'_, C> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        self.write(&mut FmtWrapper(f))
+    }
+}
+
+/// Lets `ufmt` write to a `core::fmt::Formatter`.
+struct FmtWrapper<'a, 'b>(&'a mut core::fmt::Formatter<'b>);
+
+impl uWrite for FmtWrapper<'_, '_> {
+    type Error = core::fmt::Error;
+
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.0.write_str(s)
+    }
+}
+
+/// Writes as much as fits into a byte buffer, without splitting characters.
+///
+/// Once something was cut off, everything after it is dropped, even if it would still fit.
+pub(crate) struct Truncate<'a> {
+    buffer: &'a mut [u8],
+    pub(crate) len: usize,
+    full: bool,
+}
+
+impl<'a> Truncate<'a> {
+    pub(crate) fn new(buffer: &'a mut [u8]) -> Self {
+        Truncate {
+            buffer,
+            len: 0,
+            full: false,
+        }
+    }
+}
+
+impl Write for Truncate<'_> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        if self.full {
+            return Ok(());
+        }
+        let mut end = s.len().min(self.buffer.len() - self.len);
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.buffer[self.len..self.len + end].copy_from_slice(&s.as_bytes()[..end]);
+        self.len += end;
+        self.full = end < s.len();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::string::{String, ToString};
+
+    /// The file name of `location`, as recorded.
+    fn recorded_file(location: &Location) -> String {
+        if cfg!(feature = "file-hash") {
+            std::format!("#{:08x}", frame::file_hash(location.file()))
+        } else {
+            location.file().to_string()
+        }
+    }
+
+    #[test]
+    fn take_returns_stored_record_once() {
+        let buffer = RecordBuffer::uninit();
+        let location = Location::caller();
+        buffer.store_parts(Some(location), format_args!("value was {}", 42));
+
+        let record = buffer.take().unwrap();
+        if cfg!(feature = "location") {
+            assert_eq!(record.file(), Some(recorded_file(location).as_str()));
+            assert_eq!(record.line(), location.line());
+        } else {
+            assert_eq!(record.file(), None);
+        }
+        if cfg!(feature = "message") {
+            assert_eq!(record.message(), "value was 42");
+        } else {
+            assert_eq!(record.message(), "");
+        }
+
+        assert!(buffer.take().is_none());
+    }
+
+    #[test]
+    fn corrupted_record_is_rejected() {
+        let buffer = RecordBuffer::uninit();
+        buffer.store_parts(None, "boom");
+        unsafe { (*(buffer.0.get() as *mut Raw)).line ^= 1 };
+        assert!(buffer.take().is_none());
+    }
+
+    #[test]
+    #[cfg(feature = "message")]
+    fn long_message_is_truncated_at_char_boundary() {
+        let buffer = RecordBuffer::uninit();
+        let message = "ä".repeat(MESSAGE_CAPACITY);
+        buffer.store_parts(None, &message);
+        let record = buffer.take().unwrap();
+        assert_eq!(record.message(), "ä".repeat(MESSAGE_CAPACITY / 2));
+        assert_eq!(
+            std::format!("{}", record.display::<crate::DefaultConfig>()),
+            record.message()
+        );
+    }
+
+    #[test]
+    fn nothing_is_written_after_a_cut() {
+        let mut buffer = [0; 8];
+        let mut truncate = Truncate::new(&mut buffer);
+        _ = truncate.write_str("value ");
+        _ = truncate.write_str("was ");
+        _ = truncate.write_str("42");
+        let len = truncate.len;
+        assert_eq!(&buffer[..len], b"value wa");
+
+        let mut buffer = [0; 8];
+        let mut truncate = Truncate::new(&mut buffer);
+        _ = truncate.write_str("ab");
+        _ = truncate.write_str("cdefgä");
+        _ = truncate.write_str("h");
+        let len = truncate.len;
+        assert_eq!(&buffer[..len], b"abcdefg");
+    }
+
+    #[test]
+    fn displays_with_config() {
+        struct Custom;
+
+        impl Config for Custom {
+            const PREFIX: &'static str = "panicked at ";
+            const SEPARATOR: &'static str = " - ";
+            const FALLBACK_MESSAGE: &'static str = "oops";
+        }
+
+        let buffer = RecordBuffer::uninit();
+        let location = Location::caller();
+        buffer.store_parts(Some(location), "boom");
+        let record = buffer.take().unwrap();
+        let expected = match (cfg!(feature = "location"), cfg!(feature = "message")) {
+            (true, true) => std::format!(
+                "panicked at {}:{}:{} - boom",
+                recorded_file(location),
+                location.line(),
+                location.column()
+            ),
+            (true, false) => std::format!(
+                "panicked at {}:{}:{}",
+                recorded_file(location),
+                location.line(),
+                location.column()
+            ),
+            (false, true) => "boom".into(),
+            (false, false) => "oops".into(),
+        };
+        assert_eq!(std::format!("{}", record.display::<Custom>()), expected);
+
+        let mut text = crate::tests::Buffer(String::new());
+        ufmt::uwrite!(text, "{}", record.display::<Custom>()).unwrap();
+        assert_eq!(text.0, expected);
+    }
+}
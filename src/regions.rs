@@ -0,0 +1,244 @@
+use crate::frame::{self, Hex, HexByte};
+use crate::Config;
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use portable_atomic::{AtomicU8, Ordering};
+use ufmt::uWrite;
+
+/// A named piece of memory, dumped by the panic handler.
+///
+/// Register regions with the `dump_on_panic` function defined by [`impl_panic_handler`](crate::impl_panic_handler).
+#[derive(Clone, Copy, Debug)]
+pub struct Region {
+    name: &'static str,
+    start: *const u8,
+    len: usize,
+}
+
+// The region is only ever read, by the panic handler.
+unsafe impl Send for Region {}
+unsafe impl Sync for Region {}
+
+impl Region {
+    /// Covers the memory of `value`, e.g. a `#[repr(C)]` struct of integers, or a `[u8; N]` buffer.
+    ///
+    /// The bytes are read as they are at the time of the panic, so state which keeps changing can be dumped
+    /// through its `Mutex<Cell<_>>`.
+    ///
+    /// # Safety
+    ///
+    /// Every byte of `value` must be initialized whenever the panic handler may run: plain data without padding
+    /// (`#[repr(C)]` structs of integers and arrays), `[u8; N]` buffers, or `MaybeUninit` buffers which have been
+    /// written. Types with padding, enums with fields, `RefCell`s and references don't qualify.
+    ///
+    /// The bytes are read while the rest of the program is stopped wherever it panicked. The memory must not be
+    /// borrowed mutably at that point, which a `&mut` taken from a `RefCell` or a `static mut` easily is.
+    pub unsafe fn new<T: ?Sized>(name: &'static str, value: &'static T) -> Self {
+        Region {
+            name,
+            start: value as *const T as *const u8,
+            len: core::mem::size_of_val(value),
+        }
+    }
+
+    /// Covers `len` bytes starting at `start`, e.g. those of a `static mut`.
+    ///
+    /// # Safety
+    ///
+    /// The memory must stay valid to read for the rest of the program, and the requirements of [`Region::new`]
+    /// apply to its bytes.
+    pub const unsafe fn from_raw_parts(name: &'static str, start: *const u8, len: usize) -> Self {
+        Region { name, start, len }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Where the region starts, as printed in the report.
+    pub(crate) fn address(&self) -> u32 {
+        self.start as u32
+    }
+
+    /// Reads the bytes of the region.
+    pub(crate) fn bytes(&self) -> impl Iterator<Item = u8> + '_ {
+        // SAFETY: the memory is valid to read (see the constructors). It may be changed by others at any time,
+        // hence the volatile reads.
+        (0..self.len).map(|i| unsafe { core::ptr::read_volatile(self.start.add(i)) })
+    }
+}
+
+/// The slot is free.
+const EMPTY: u8 = 0;
+/// `add` is writing the region into the slot.
+const ADDING: u8 = 1;
+/// The slot holds a region.
+const ADDED: u8 = 2;
+
+struct Slot {
+    state: AtomicU8,
+    region: UnsafeCell<MaybeUninit<Region>>,
+}
+
+/// Holds up to `N` regions to dump on a panic.
+///
+/// [`impl_panic_handler`](crate::impl_panic_handler) defines a static of this type, with
+/// [`Config::MAX_REGIONS`] slots.
+pub struct Regions<const N: usize> {
+    slots: [Slot; N],
+}
+
+// Access to the regions is guarded by the state of their slots.
+unsafe impl<const N: usize> Sync for Regions<N> {}
+
+impl<const N: usize> Regions<N> {
+    pub const fn new() -> Self {
+        // Only used to initialize the array, every slot is a copy.
+        #[allow(clippy::declare_interior_mutable_const)]
+        const SLOT: Slot = Slot {
+            state: AtomicU8::new(EMPTY),
+            region: UnsafeCell::new(MaybeUninit::uninit()),
+        };
+        Regions { slots: [SLOT; N] }
+    }
+
+    /// Adds a region, which is dumped from now on.
+    ///
+    /// Gives the region back if all slots are taken.
+    pub fn add(&self, region: Region) -> Result<(), Region> {
+        for slot in &self.slots {
+            if slot
+                .state
+                .compare_exchange(EMPTY, ADDING, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+            {
+                // SAFETY: the `ADDING` state gives us exclusive access, and only ever happens once per slot.
+                unsafe { (*slot.region.get()).write(region) };
+                slot.state.store(ADDED, Ordering::Release);
+                return Ok(());
+            }
+        }
+        Err(region)
+    }
+
+    /// The regions added so far.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &Region> {
+        self.slots
+            .iter()
+            .filter(|slot| slot.state.load(Ordering::Acquire) == ADDED)
+            // SAFETY: the region was written before the state became `ADDED`, and is never written again.
+            .map(|slot| unsafe { (*slot.region.get()).assume_init_ref() })
+    }
+}
+
+impl<const N: usize> Default for Regions<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Dumps every region: a line with its name, address and length, followed by lines of 16 bytes in hex.
+pub(crate) fn print_regions<C: Config, W: uWrite + ?Sized, const N: usize>(
+    w: &mut W,
+    regions: &Regions<N>,
+) {
+    for region in regions.iter() {
+        if cfg!(feature = "binary") {
+            frame::write_region(
+                w,
+                region.name,
+                region.address(),
+                region.bytes(),
+                C::LINE_ENDING,
+            );
+            continue;
+        }
+
+        _ = ufmt::uwrite!(
+            w,
+            "Region {} at 0x{} ({} {}){}",
+            region.name,
+            Hex(region.address()),
+            region.len,
+            if region.len == 1 { "byte" } else { "bytes" },
+            C::LINE_ENDING
+        );
+        for (i, byte) in region.bytes().enumerate() {
+            if i % 16 == 0 {
+                if i > 0 {
+                    _ = w.write_str(C::LINE_ENDING);
+                }
+                _ = ufmt::uwrite!(w, "{}", Hex((region.start as usize + i) as u32));
+            }
+            _ = ufmt::uwrite!(w, " {}", HexByte(byte));
+        }
+        if !region.is_empty() {
+            _ = w.write_str(C::LINE_ENDING);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+
+    static STATE: [u8; 20] = [
+        0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 0xa0, 0xb1, 0xc2, 0xd3,
+    ];
+    static MODE: u8 = 7;
+
+    #[test]
+    fn rejects_regions_when_full() {
+        let regions = Regions::<1>::new();
+        assert!(regions.add(unsafe { Region::new("state", &STATE) }).is_ok());
+        let rejected = regions
+            .add(unsafe { Region::new("mode", &MODE) })
+            .unwrap_err();
+        assert_eq!(rejected.name(), "mode");
+        assert_eq!(regions.iter().count(), 1);
+    }
+
+    #[test]
+    #[cfg(not(feature = "binary"))]
+    fn dumps_regions() {
+        use crate::tests::Buffer;
+        use crate::DefaultConfig;
+        use std::format;
+        use std::string::String;
+
+        let regions = Regions::<2>::new();
+        regions
+            .add(unsafe { Region::new("state", &STATE) })
+            .unwrap();
+        regions.add(unsafe { Region::new("mode", &MODE) }).unwrap();
+
+        let mut buffer = Buffer(String::new());
+        print_regions::<DefaultConfig, _, 2>(&mut buffer, &regions);
+
+        let (state, mode) = (
+            STATE.as_ptr() as usize as u32,
+            &MODE as *const u8 as usize as u32,
+        );
+        assert_eq!(
+            buffer.0,
+            format!(
+                "Region state at 0x{state:08x} (20 bytes)\r\n\
+                 {state:08x} 00 01 02 03 04 05 06 07 08 09 0a 0b 0c 0d 0e 0f\r\n\
+                 {:08x} a0 b1 c2 d3\r\n\
+                 Region mode at 0x{mode:08x} (1 byte)\r\n\
+                 {mode:08x} 07\r\n",
+                state + 16
+            )
+        );
+    }
+}
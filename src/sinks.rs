@@ -0,0 +1,327 @@
+//! Sinks which print to a debug probe instead of a serial port: ARM semihosting (with the `semihosting` feature)
+//! and SEGGER RTT (with the `rtt` feature).
+//!
+//! Use the sink as port type when invoking the macro, and share it like a port:
+//! ```ignore
+//! panic_serial::impl_panic_handler!(panic_serial::Rtt);
+//!
+//! let mut log = share_serial_port_with_panic(panic_serial::Rtt::take().unwrap()).unwrap();
+//! ```
+
+use ufmt::uWrite;
+
+#[cfg(feature = "rtt")]
+use core::cell::UnsafeCell;
+#[cfg(feature = "rtt")]
+use core::convert::Infallible;
+#[cfg(feature = "rtt")]
+use portable_atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// Writes to the console of the debugger through ARM semihosting, on Cortex-M.
+///
+/// Every write stops the core until the debugger has handled it, which is slow, but nothing gets lost. Without a
+/// debugger attached, a write raises a HardFault, so only share this while a probe is attached.
+#[cfg(feature = "semihosting")]
+#[derive(Debug, Default)]
+pub struct Semihosting {
+    /// Handle of the console, opened with the first write.
+    handle: Option<usize>,
+}
+
+/// Error of [`Semihosting`]: the debugger did not take the output (or the target is not a Cortex-M).
+#[cfg(feature = "semihosting")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SemihostingError;
+
+#[cfg(feature = "semihosting")]
+const SYS_OPEN: usize = 0x01;
+#[cfg(feature = "semihosting")]
+const SYS_WRITE: usize = 0x05;
+
+#[cfg(feature = "semihosting")]
+impl Semihosting {
+    /// Creates the sink. The console is opened with the first write.
+    pub const fn new() -> Self {
+        Semihosting { handle: None }
+    }
+
+    /// Does nothing, every write is done once it returns.
+    pub fn flush(&mut self) -> Result<(), SemihostingError> {
+        Ok(())
+    }
+
+    fn handle(&mut self) -> Result<usize, SemihostingError> {
+        if let Some(handle) = self.handle {
+            return Ok(handle);
+        }
+        // ":tt" is the console, mode 4 opens it for writing ("w"), 3 is the length of the name.
+        // (`c""` literals would need Rust 1.77.)
+        #[allow(clippy::manual_c_str_literals)]
+        let args = [b":tt\0".as_ptr() as usize, 4, 3];
+        // SAFETY: the arguments are valid for `SYS_OPEN`.
+        let handle = unsafe { semihosting_call(SYS_OPEN, args.as_ptr()) };
+        if handle == usize::MAX {
+            return Err(SemihostingError);
+        }
+        self.handle = Some(handle);
+        Ok(handle)
+    }
+}
+
+#[cfg(feature = "semihosting")]
+impl uWrite for Semihosting {
+    type Error = SemihostingError;
+
+    fn write_str(&mut self, s: &str) -> Result<(), SemihostingError> {
+        let args = [self.handle()?, s.as_ptr() as usize, s.len()];
+        // SAFETY: the arguments are valid for `SYS_WRITE`. It returns the number of bytes which were not written.
+        match unsafe { semihosting_call(SYS_WRITE, args.as_ptr()) } {
+            0 => Ok(()),
+            _ => Err(SemihostingError),
+        }
+    }
+}
+
+/// Makes a semihosting call, passing the `args` block to the debugger.
+///
+/// # Safety
+///
+/// `args` must be what the `operation` expects.
+#[cfg(all(feature = "semihosting", cortex_m))]
+unsafe fn semihosting_call(operation: usize, args: *const usize) -> usize {
+    let result;
+    core::arch::asm!(
+        "bkpt #0xab",
+        inout("r0") operation => result,
+        in("r1") args,
+        options(nostack, preserves_flags),
+    );
+    result
+}
+
+/// `bkpt 0xab` only makes a semihosting call on Cortex-M (elsewhere it is a plain breakpoint, or does not exist),
+/// so on other targets every call fails.
+#[cfg(all(feature = "semihosting", not(cortex_m)))]
+unsafe fn semihosting_call(_operation: usize, _args: *const usize) -> usize {
+    usize::MAX
+}
+
+/// Size of the buffer of the RTT channel, in bytes.
+#[cfg(feature = "rtt")]
+pub const RTT_BUFFER_SIZE: usize = 1024;
+
+/// Writes to up channel 0 of SEGGER RTT, which the probe reads from the RAM while the firmware runs
+/// (e.g. `probe-rs attach`).
+///
+/// This crate brings its own RTT control block (the `_SEGGER_RTT` symbol) with just this channel, so it cannot be
+/// combined with other RTT crates like `rtt-target` or `defmt-rtt`. Writes never block by default: what does not
+/// fit in the buffer ([`RTT_BUFFER_SIZE`] bytes) until the probe reads it is cut off, unless the probe puts the
+/// channel into blocking mode.
+///
+/// There is only one channel, so there is only one `Rtt`: get it with [`take`](Rtt::take).
+#[cfg(feature = "rtt")]
+#[derive(Debug)]
+pub struct Rtt(());
+
+/// Whether the `Rtt` was handed out.
+#[cfg(feature = "rtt")]
+static RTT_TAKEN: AtomicBool = AtomicBool::new(false);
+
+#[cfg(feature = "rtt")]
+impl Rtt {
+    /// Returns the `Rtt` the first time it is called, `None` afterwards.
+    pub fn take() -> Option<Self> {
+        (!RTT_TAKEN.swap(true, Ordering::Relaxed)).then_some(Rtt(()))
+    }
+
+    /// Does nothing, the probe reads the buffer whenever it likes.
+    pub fn flush(&mut self) -> Result<(), Infallible> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "rtt")]
+impl uWrite for Rtt {
+    type Error = Infallible;
+
+    fn write_str(&mut self, s: &str) -> Result<(), Infallible> {
+        _SEGGER_RTT.up.write(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// The part of the channel flags which sets what happens when the buffer is full.
+#[cfg(feature = "rtt")]
+const MODE_MASK: usize = 3;
+/// Skips writes which do not fit in the buffer as a whole.
+#[cfg(feature = "rtt")]
+const NO_BLOCK_SKIP: usize = 0;
+/// Writes as much as fits in the buffer.
+#[cfg(feature = "rtt")]
+const NO_BLOCK_TRIM: usize = 1;
+/// Waits for the probe to make room in the buffer.
+#[cfg(feature = "rtt")]
+const BLOCK_IF_FULL: usize = 2;
+
+/// The RTT control block, which the probe finds by its id. The layout is that of SEGGER's C structs on 32 bit targets.
+#[cfg(feature = "rtt")]
+#[repr(C)]
+struct ControlBlock {
+    id: [u8; 16],
+    max_up: usize,
+    max_down: usize,
+    up: Channel,
+}
+
+/// An up channel: a ring buffer which the firmware writes to, and the probe reads from.
+#[cfg(feature = "rtt")]
+#[repr(C)]
+struct Channel {
+    name: *const u8,
+    buffer: *mut u8,
+    size: usize,
+    /// Where the firmware writes next.
+    write: AtomicUsize,
+    /// Where the probe reads next, changed by the probe.
+    read: AtomicUsize,
+    /// The mode, which the probe may change.
+    flags: AtomicUsize,
+}
+
+// The buffer is only written through the one `Rtt` handed out by `Rtt::take`, which is used from one place at
+// a time, like any port (see `SharedPort`). The probe only touches `read` and `flags`, which are atomic.
+#[cfg(feature = "rtt")]
+unsafe impl Sync for ControlBlock {}
+
+#[cfg(feature = "rtt")]
+struct Buffer(UnsafeCell<[u8; RTT_BUFFER_SIZE]>);
+
+// Only accessed through the control block.
+#[cfg(feature = "rtt")]
+unsafe impl Sync for Buffer {}
+
+#[cfg(feature = "rtt")]
+static BUFFER: Buffer = Buffer(UnsafeCell::new([0; RTT_BUFFER_SIZE]));
+
+#[cfg(feature = "rtt")]
+#[no_mangle]
+#[allow(clippy::manual_c_str_literals)]
+static _SEGGER_RTT: ControlBlock = ControlBlock {
+    id: *b"SEGGER RTT\0\0\0\0\0\0",
+    max_up: 1,
+    max_down: 0,
+    up: Channel {
+        name: b"Terminal\0".as_ptr(),
+        buffer: BUFFER.0.get() as *mut u8,
+        size: RTT_BUFFER_SIZE,
+        write: AtomicUsize::new(0),
+        read: AtomicUsize::new(0),
+        flags: AtomicUsize::new(NO_BLOCK_TRIM),
+    },
+};
+
+#[cfg(feature = "rtt")]
+impl Channel {
+    /// Writes as much of `bytes` as the mode of the channel allows.
+    fn write(&self, mut bytes: &[u8]) {
+        let mode = self.flags.load(Ordering::Relaxed) & MODE_MASK;
+        let mut write = self.write.load(Ordering::Relaxed);
+        if mode == NO_BLOCK_SKIP && self.free(write) < bytes.len() {
+            return;
+        }
+        while !bytes.is_empty() {
+            let free = self.free(write);
+            if free == 0 {
+                if mode == BLOCK_IF_FULL {
+                    core::hint::spin_loop();
+                    continue;
+                }
+                return;
+            }
+            // Up to the end of the buffer at once, the rest wraps around.
+            let len = bytes.len().min(free).min(self.size - write);
+            // SAFETY: `write..write + len` is within the buffer, and not being read by the probe.
+            unsafe { core::ptr::copy_nonoverlapping(bytes.as_ptr(), self.buffer.add(write), len) };
+            write = (write + len) % self.size;
+            self.write.store(write, Ordering::Release);
+            bytes = &bytes[len..];
+        }
+    }
+
+    /// The number of bytes which can be written before reaching what the probe has not read yet.
+    fn free(&self, write: usize) -> usize {
+        let read = self.read.load(Ordering::Acquire);
+        // One byte stays free, or a full buffer would look empty.
+        (read + self.size - write - 1) % self.size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(any(feature = "rtt", feature = "semihosting"))]
+    use super::*;
+
+    #[cfg(feature = "rtt")]
+    fn channel(buffer: &mut [u8], mode: usize) -> Channel {
+        Channel {
+            name: core::ptr::null(),
+            buffer: buffer.as_mut_ptr(),
+            size: buffer.len(),
+            write: AtomicUsize::new(0),
+            read: AtomicUsize::new(0),
+            flags: AtomicUsize::new(mode),
+        }
+    }
+
+    #[test]
+    #[cfg(all(feature = "semihosting", not(cortex_m)))]
+    fn semihosting_fails_off_cortex_m() {
+        assert_eq!(
+            unsafe { semihosting_call(SYS_OPEN, core::ptr::null()) },
+            usize::MAX
+        );
+
+        let mut semihosting = Semihosting::new();
+        assert_eq!(semihosting.write_str("Panic"), Err(SemihostingError));
+        assert_eq!(semihosting.handle, None);
+        assert_eq!(semihosting.flush(), Ok(()));
+    }
+
+    #[test]
+    #[cfg(feature = "rtt")]
+    fn rtt_is_taken_once() {
+        assert!(Rtt::take().is_some());
+        assert!(Rtt::take().is_none());
+    }
+
+    #[test]
+    #[cfg(feature = "rtt")]
+    fn rtt_wraps_around() {
+        let mut buffer = [0; 8];
+        let channel = channel(&mut buffer, NO_BLOCK_TRIM);
+        channel.write(b"Panic");
+        // The probe reads the first four bytes.
+        channel.read.store(4, Ordering::Relaxed);
+        channel.write(b" at ");
+        assert_eq!(channel.write.load(Ordering::Relaxed), 1);
+        assert_eq!(&buffer, b" anic at");
+    }
+
+    #[test]
+    #[cfg(feature = "rtt")]
+    fn rtt_trims_or_skips_when_full() {
+        let mut buffer = [0; 8];
+        let trim = channel(&mut buffer, NO_BLOCK_TRIM);
+        trim.write(b"Panic at src/main.rs");
+        assert_eq!(trim.write.load(Ordering::Relaxed), 7);
+        assert_eq!(&buffer[..7], b"Panic a");
+
+        let mut buffer = [0; 8];
+        let skip = channel(&mut buffer, NO_BLOCK_SKIP);
+        skip.write(b"Panic");
+        skip.write(b" at ");
+        skip.write(b"!");
+        assert_eq!(skip.write.load(Ordering::Relaxed), 6);
+        assert_eq!(&buffer[..6], b"Panic!");
+    }
+}
@@ -0,0 +1,265 @@
+//! Checks the exact output of the panic handler.
+//!
+//! The expected output depends on the enabled features, so run these for every combination:
+//! ```sh
+//! cargo test
+//! cargo test --features location
+//! cargo test --features message
+//! cargo test --features full
+//! cargo test --features full,file-hash
+//! ```
+//! With the `binary` feature, frames are printed instead, which are checked in the `frame` module.
+//!
+//! A `PanicInfo` cannot be constructed outside of `core`, so the panics are either real ones, caught on the
+//! host (where the panic hook sees the same location and message the panic handler would), or made up
+//! from a location and `format_args!`.
+
+extern crate std;
+
+use super::*;
+use core::convert::Infallible;
+use core::panic::Location;
+use std::string::String;
+
+/// Collects everything written, like a terminal on the other end of the serial line would.
+pub(crate) struct Buffer(pub(crate) String);
+
+impl uWrite for Buffer {
+    type Error = Infallible;
+
+    fn write_str(&mut self, s: &str) -> Result<(), Infallible> {
+        self.0.push_str(s);
+        Ok(())
+    }
+}
+
+#[test]
+fn custom_formatter() {
+    struct Json;
+
+    impl PanicFormatter for Json {
+        fn format<C: Config, W: uWrite, M: Display>(
+            w: &mut W,
+            location: Option<&Location<'_>>,
+            message: M,
+        ) {
+            _ = w.write_str("{\"message\":\"");
+            _ = write!(WriteWrapper(w), "{}", message);
+            if let Some(location) = location {
+                _ = ufmt::uwrite!(w, "\",\"line\":{}", location.line());
+            }
+            _ = w.write_str("}\n");
+        }
+    }
+
+    let location = Location::caller();
+    let mut buffer = Buffer(String::new());
+    Json::format::<DefaultConfig, _, _>(&mut buffer, Some(location), format_args!("value {}", 1));
+    assert_eq!(
+        buffer.0,
+        std::format!("{{\"message\":\"value 1\",\"line\":{}}}\n", location.line())
+    );
+}
+
+#[test]
+fn finishes_partial_line() {
+    struct Quiet;
+
+    impl Config for Quiet {
+        const SENTINEL: &'static str = "";
+    }
+
+    let mut buffer = Buffer(String::from("state 3, temp"));
+    break_line::<DefaultConfig, _>(&mut buffer);
+    assert_eq!(buffer.0, "state 3, temp\r\n!!! PANIC\r\n");
+
+    let mut buffer = Buffer(String::from("state 3, temp"));
+    break_line::<Quiet, _>(&mut buffer);
+    assert_eq!(buffer.0, "state 3, temp\r\n");
+}
+
+#[cfg(not(feature = "binary"))]
+mod text {
+    use super::*;
+    use std::cell::{Cell, RefCell};
+    use std::format;
+    use std::panic::{self, Location};
+    use std::sync::Once;
+
+    fn render<M: Display>(location: Option<&Location>, message: M) -> String {
+        render_with::<DefaultConfig, M>(location, message)
+    }
+
+    fn render_with<C: Config, M: Display>(location: Option<&Location>, message: M) -> String {
+        let mut buffer = Buffer(String::new());
+        print_report::<C, _, _>(&mut buffer, location, message);
+        buffer.0
+    }
+
+    std::thread_local! {
+        static CATCHING: Cell<bool> = const { Cell::new(false) };
+        static CAUGHT: RefCell<Option<(String, String)>> = const { RefCell::new(None) };
+    }
+
+    /// Runs `f`, which must panic, and returns what the panic handler prints for it, along with the `Panic at` location.
+    ///
+    /// Panics elsewhere (like failing tests) still go to the previous hook.
+    fn catch<F: FnOnce() + panic::UnwindSafe>(f: F) -> (String, String) {
+        static HOOK: Once = Once::new();
+        HOOK.call_once(|| {
+            let previous = panic::take_hook();
+            panic::set_hook(std::boxed::Box::new(move |info| {
+                if !CATCHING.with(Cell::get) {
+                    return previous(info);
+                }
+                let location = info.location().unwrap();
+                let output = render(Some(location), info.payload_as_str().unwrap_or_default());
+                let location = here(location);
+                CAUGHT.with(|caught| *caught.borrow_mut() = Some((output, location)));
+            }))
+        });
+
+        CATCHING.with(|catching| catching.set(true));
+        let result = panic::catch_unwind(f);
+        CATCHING.with(|catching| catching.set(false));
+        assert!(result.is_err(), "closure did not panic");
+        CAUGHT.with(|caught| caught.borrow_mut().take()).unwrap()
+    }
+
+    /// What the handler is expected to print with the enabled features.
+    fn expected(location: Option<&str>, message: &str) -> String {
+        expected_with::<DefaultConfig>(location, message)
+    }
+
+    fn expected_with<C: Config>(location: Option<&str>, message: &str) -> String {
+        let (prefix, separator, end) = (C::PREFIX, C::SEPARATOR, C::LINE_ENDING);
+        match (
+            cfg!(feature = "location"),
+            cfg!(feature = "message"),
+            location,
+        ) {
+            (true, true, Some(location)) => format!("{prefix}{location}{separator}{message}{end}"),
+            (true, false, Some(location)) => format!("{prefix}{location}{end}"),
+            (true, true, None) | (false, true, _) => format!("{message}{end}"),
+            (true, false, None) => String::new(),
+            (false, false, _) => format!("{}{end}", C::FALLBACK_MESSAGE),
+        }
+    }
+
+    /// How the handler prints the file name with the enabled features.
+    fn file(file: &str) -> String {
+        if cfg!(feature = "file-hash") {
+            format!("#{:08x}", frame::file_hash(file))
+        } else {
+            String::from(file)
+        }
+    }
+
+    fn here(location: &Location) -> String {
+        format!(
+            "{}:{}:{}",
+            file(location.file()),
+            location.line(),
+            location.column()
+        )
+    }
+
+    #[test]
+    fn literal_message() {
+        let location = Location::caller();
+        assert_eq!(
+            render(Some(location), format_args!("something went wrong")),
+            expected(Some(&here(location)), "something went wrong")
+        );
+    }
+
+    #[test]
+    fn formatted_message() {
+        let location = Location::caller();
+        let (index, len) = (7, 3);
+        assert_eq!(
+            render(
+                Some(location),
+                format_args!("index {} out of range for slice of length {}", index, len)
+            ),
+            expected(
+                Some(&here(location)),
+                "index 7 out of range for slice of length 3"
+            )
+        );
+    }
+
+    #[test]
+    fn custom_template() {
+        struct Custom;
+
+        impl Config for Custom {
+            const LINE_ENDING: &'static str = "\n";
+            const PREFIX: &'static str = "panicked at ";
+            const SEPARATOR: &'static str = " - ";
+            const FALLBACK_MESSAGE: &'static str = "crashed";
+        }
+
+        let location = Location::caller();
+        assert_eq!(
+            render_with::<Custom, _>(Some(location), format_args!("value {}", 1)),
+            expected_with::<Custom>(Some(&here(location)), "value 1")
+        );
+        assert_eq!(
+            render_with::<Custom, _>(None, "oops"),
+            expected_with::<Custom>(None, "oops")
+        );
+    }
+
+    #[test]
+    fn missing_location() {
+        assert_eq!(
+            render(None, format_args!("value was {:?}", Some(42))),
+            expected(None, "value was Some(42)")
+        );
+    }
+
+    #[test]
+    fn caught_literal_panic() {
+        let (output, location) = catch(|| panic!("boom"));
+        assert!(location.starts_with(&file(file!())));
+        assert_eq!(output, expected(Some(&location), "boom"));
+    }
+
+    #[test]
+    fn caught_formatted_panic() {
+        let (output, location) = catch(|| panic!("state {} is invalid", 3));
+        assert_eq!(output, expected(Some(&location), "state 3 is invalid"));
+    }
+
+    #[test]
+    fn caught_unwrap_on_err() {
+        let (output, location) = catch(|| {
+            let result: Result<(), u8> = core::hint::black_box(Err(42));
+            result.unwrap();
+        });
+        assert_eq!(
+            output,
+            expected(
+                Some(&location),
+                "called `Result::unwrap()` on an `Err` value: 42"
+            )
+        );
+    }
+
+    #[test]
+    fn caught_index_out_of_bounds() {
+        let (output, location) = catch(|| {
+            let values = [1, 2, 3];
+            let index = core::hint::black_box(7);
+            _ = values[index];
+        });
+        assert_eq!(
+            output,
+            expected(
+                Some(&location),
+                "index out of bounds: the len is 3 but the index is 7"
+            )
+        );
+    }
+}
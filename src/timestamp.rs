@@ -0,0 +1,82 @@
+//! Telling when the panic happened.
+
+use crate::frame;
+use crate::Config;
+use ufmt::uWrite;
+
+/// Prints [`Config::timestamp`] in brackets, as prefix of the report, if there is one.
+pub(crate) fn print_timestamp<C: Config, W: uWrite + ?Sized>(w: &mut W) {
+    let Some(timestamp) = C::timestamp() else {
+        return;
+    };
+    if cfg!(feature = "binary") {
+        frame::write_timestamp(w, timestamp, C::LINE_ENDING);
+    } else {
+        _ = ufmt::uwrite!(w, "[{}] ", timestamp);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::tests::Buffer;
+    use std::string::String;
+
+    struct Clocked;
+
+    impl Config for Clocked {
+        fn timestamp() -> Option<u64> {
+            Some(86_400_123)
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "binary"))]
+    fn prints_timestamp() {
+        use crate::DefaultConfig;
+
+        let mut buffer = Buffer(String::new());
+        print_timestamp::<Clocked, _>(&mut buffer);
+        assert_eq!(buffer.0, "[86400123] ");
+
+        let mut buffer = Buffer(String::new());
+        print_timestamp::<DefaultConfig, _>(&mut buffer);
+        assert_eq!(buffer.0, "");
+    }
+
+    #[test]
+    #[cfg(not(feature = "binary"))]
+    fn leaves_custom_formatter_alone() {
+        use crate::{DefaultFormatter, PanicFormatter};
+        use core::fmt::Display;
+        use core::panic::Location;
+
+        struct Bare;
+
+        impl PanicFormatter for Bare {
+            fn format<C: Config, W: uWrite, M: Display>(w: &mut W, _: Option<&Location<'_>>, _: M) {
+                _ = w.write_str("bare");
+            }
+        }
+
+        let mut buffer = Buffer(String::new());
+        DefaultFormatter::format::<Clocked, _, _>(&mut buffer, None, "boom");
+        assert!(buffer.0.starts_with("[86400123] "));
+
+        let mut buffer = Buffer(String::new());
+        Bare::format::<Clocked, _, _>(&mut buffer, None, "boom");
+        assert_eq!(buffer.0, "bare");
+    }
+
+    #[test]
+    #[cfg(feature = "binary")]
+    fn prints_frame() {
+        let mut buffer = Buffer(String::new());
+        print_timestamp::<Clocked, _>(&mut buffer);
+        let mut expected = Buffer(String::new());
+        frame::write_timestamp(&mut expected, 86_400_123, "\r\n");
+        assert_eq!(buffer.0, expected.0);
+    }
+}